@@ -1,6 +1,11 @@
 use chess_minimax::{
     board::{self, Board},
+    clock::{Clock, TimeControl},
+    eval::Evaluation,
+    game::{Game, GameOutcome},
     piece::{Piece, PieceKind},
+    search::{Search, SearchLimits},
+    transposition::TranspositionTable,
     Pos,
     Side
 };
@@ -20,6 +25,7 @@ use gtk::{
     Align,
     Box as GtkBox,
     Button,
+    ComboBoxText,
     CssProvider,
     DestDefaults,
     Dialog,
@@ -45,12 +51,16 @@ use std::{
         Arc
     },
     thread,
-    time::{Duration, Instant}
+    time::Duration
 };
 
 const ICON_SIZE: i32 = 60;
-const TIMEOUT: u64 = 3;
+const TIME_CONTROL: TimeControl = TimeControl::Increment {
+    time: Duration::from_secs(5 * 60),
+    increment: Duration::from_secs(3)
+};
 const SIDE_PLAYER: Side = Side::White;
+const TITLE: &str = "Chess Minimax";
 
 struct Data {
     black_pawn: Pixbuf,
@@ -125,6 +135,25 @@ fn redraw(grid: &Grid, board: &Board, data: &Data) {
         }
     }
 }
+fn show_outcome(window: &Window, outcome: GameOutcome) {
+    let text = match outcome.winner() {
+        Some(SIDE_PLAYER) => "You won!".to_string(),
+        Some(_) => "You lost!".to_string(),
+        None => format!("It's a {}.", outcome)
+    };
+    let dialog = Dialog::new_with_buttons(
+        Some("Game over!"),
+        Some(window),
+        DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+        &[
+            ("Ok", ResponseType::Ok.into())
+        ]
+    );
+    dialog.get_content_area().add(&Label::new(&*text));
+    dialog.show_all();
+    dialog.run();
+    dialog.destroy();
+}
 fn main() {
     if let Err(err) = gtk::init() {
         eprintln!("failed to init gtk: {}", err);
@@ -134,32 +163,42 @@ fn main() {
     let data = Rc::new(Data::default());
 
     let exit = Arc::new(AtomicBool::new(false));
-    let (tx_move, rx_move) = mpsc::channel::<Board>();
+    let (tx_move, rx_move) = mpsc::channel::<(Game, SearchLimits, Evaluation)>();
     let (tx_reply, rx_reply) = mpsc::channel();
     let thread = {
         let exit = Arc::clone(&exit);
         thread::spawn(move || {
-            for mut board in rx_move {
-                let mut result = None;
-                for depth in 1.. {
-                    println!("Trying depth {}", depth);
-                    if let Some(new) = board.minimax(depth, !SIDE_PLAYER, Some(&exit)) {
-                        result = Some(new);
-                    }
-                    if exit.swap(false, Ordering::SeqCst) {
-                        break;
-                    }
+            let mut table = TranspositionTable::default();
+            let mut evaluation = Evaluation::default();
+            let mut evaluator = evaluation.evaluator();
+            for (mut game, limits, chosen) in rx_move {
+                // Forget any stop request that came in after the last search
+                exit.store(false, Ordering::SeqCst);
+                if chosen != evaluation {
+                    evaluation = chosen;
+                    evaluator = evaluation.evaluator();
                 }
+                let mut search = Search::new(limits, &mut table);
+                search.evaluator = Some(&mut *evaluator);
+                search.exit = Some(&exit);
+                let result = game.search(&mut search, |iteration| {
+                    let result = iteration.result;
+                    let pv: Vec<_> = result.pv.iter().map(|m| m.to_string()).collect();
+                    println!("Depth {}, score {}: {}", result.depth, result.score, pv.join(" "));
+                    println!("Searched {} nodes, {} in quiescence", iteration.stats.nodes, iteration.stats.qnodes);
+                });
                 tx_reply.send(result).unwrap();
             }
         })
     };
 
     let players_turn = Rc::new(Cell::new(true));
-    let turn_start = Rc::new(Cell::new(None));
+    let evaluation = Rc::new(Cell::new(Evaluation::default()));
+    let clock = Rc::new(RefCell::new(Clock::new(TIME_CONTROL)));
+    clock.borrow_mut().start(SIDE_PLAYER);
 
     let window = Window::new(WindowType::Toplevel);
-    window.set_title("Chess Minimax");
+    window.set_title(TITLE);
     window.set_default_size(ICON_SIZE * board::WIDTH as i32, ICON_SIZE * board::WIDTH as i32 + 200);
 
     let css = CssProvider::new();
@@ -184,11 +223,11 @@ fn main() {
         Inhibit(false)
     });
 
-    let board = Rc::new(RefCell::new(Board::new()));
+    let game = Rc::new(RefCell::new(Game::new(Board::new())));
     let main = GtkBox::new(Orientation::Vertical, 0);
     let grid = Grid::new();
 
-    for (y, row) in board.borrow().iter().enumerate() {
+    for (y, row) in game.borrow().board().iter().enumerate() {
         for (x, piece) in row.iter().enumerate() {
             let to = Pos(x as i8, y as i8);
 
@@ -232,11 +271,12 @@ fn main() {
             });
 
             {
-                let board = Rc::clone(&board);
+                let game = Rc::clone(&game);
                 let data = Rc::clone(&data);
                 let grid = grid.clone();
                 let players_turn = Rc::clone(&players_turn);
-                let turn_start = Rc::clone(&turn_start);
+                let clock = Rc::clone(&clock);
+                let evaluation = Rc::clone(&evaluation);
                 let tx_move = tx_move.clone();
                 let window = window.clone();
                 button.connect_drag_data_received(move |_button, ctx, _x, _y, pos, _info, time| {
@@ -247,55 +287,39 @@ fn main() {
                         None => return
                     };
 
-                    let mut board = board.borrow_mut();
+                    let mut game = game.borrow_mut();
+                    let board = game.board();
 
                     if board.get(from).map(|p| p.side != SIDE_PLAYER).unwrap_or(true) {
                         return;
                     }
 
-                    let mut possible = false;
-                    let mut moves = board.moves_for(from);
-                    while let Some(m) = moves.next(&mut board) {
-                        if m == to {
-                            possible = true;
-                            break;
+                    let m = board.infer_move(from, to, None);
+                    if !board.is_legal(m) {
+                        if let Some(checker) = board.check(SIDE_PLAYER) {
+                            get_child(&grid, checker).get_style_context().unwrap().add_class("highlight");
                         }
-                    }
-
-                    if !possible {
                         return;
                     }
 
-                    let undo = board.move_(from, to);
-
-                    if let Some(checker) = board.check(SIDE_PLAYER) {
-                        board.undo(undo);
-                        get_child(&grid, checker).get_style_context().unwrap().add_class("highlight");
-                        return;
-                    }
+                    game.make_move(m);
 
                     players_turn.set(false);
 
-                    redraw(&grid, &board, &data);
-
-                    if board.is_checkmate(!SIDE_PLAYER) {
-                        let dialog = Dialog::new_with_buttons(
-                            Some("Checkmate!"),
-                            Some(&window),
-                            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
-                            &[
-                                ("Ok", ResponseType::Ok.into())
-                            ]
-                        );
-                        dialog.get_content_area().add(&Label::new("You won!"));
-                        dialog.show_all();
-                        dialog.run();
-                        dialog.destroy();
+                    redraw(&grid, game.board(), &data);
+
+                    if let Some(outcome) = game.outcome() {
+                        clock.borrow_mut().stop();
+                        show_outcome(&window, outcome);
                         return;
                     }
 
-                    turn_start.set(Some(Instant::now()));
-                    tx_move.send((*board).clone()).unwrap();
+                    let limits = {
+                        let mut clock = clock.borrow_mut();
+                        clock.start(!SIDE_PLAYER);
+                        clock.budget(!SIDE_PLAYER).limits()
+                    };
+                    tx_move.send(((*game).clone(), limits, evaluation.get())).unwrap();
                 });
             }
 
@@ -305,6 +329,24 @@ fn main() {
 
     grid.set_halign(Align::Center);
     main.add(&grid);
+
+    // The evaluation the computer plays with, applied from its next move on
+    let settings = GtkBox::new(Orientation::Horizontal, 5);
+    settings.add(&Label::new("Evaluation:"));
+    let evaluations = ComboBoxText::new();
+    for evaluation in &Evaluation::ALL {
+        evaluations.append_text(evaluation.name());
+    }
+    // The default comes first
+    evaluations.set_active(0);
+    evaluations.connect_changed(move |evaluations| {
+        if let Some(&chosen) = Evaluation::ALL.get(evaluations.get_active() as usize) {
+            evaluation.set(chosen);
+        }
+    });
+    settings.add(&evaluations);
+    settings.set_halign(Align::Center);
+    main.add(&settings);
     let attribution = LinkButton::new_with_label(
         "https://commons.wikimedia.org/wiki/Category:PNG_chess_pieces/Standard_transparent",
         "Chess pieces by Wikipedia user Cburnett - CC BY-SA 3.0"
@@ -317,32 +359,47 @@ fn main() {
         let exit = Arc::clone(&exit);
         let window = window.clone();
         timeout_add_seconds(1, move || {
-            if let Some(result) = rx_reply.try_recv().ok().and_then(|result| result) {
-                let mut board = board.borrow_mut();
-                board.move_(result.from, result.to);
+            // Don't keep the clock borrowed while a dialog is shown, this
+            // gets called again in the meantime
+            let flagged = {
+                let clock = clock.borrow();
+                if clock.running().is_none() {
+                    // The game is over
+                    return Continue(true);
+                }
+                window.set_title(&format!("{} - {}", TITLE, clock));
+                clock.flagged()
+            };
+
+            if let Some(side) = flagged {
+                clock.borrow_mut().stop();
+                players_turn.set(false);
+                exit.store(true, Ordering::SeqCst);
+                show_outcome(&window, GameOutcome::Timeout { winner: !side });
+            } else if let Ok(result) = rx_reply.try_recv() {
+                let mut game = game.borrow_mut();
+                // A search that's stopped before finishing its first
+                // iteration has no move to offer, so play any legal one
+                let m = match result {
+                    Some(result) => result.move_,
+                    None => {
+                        let board = game.board();
+                        match board.legal_moves(board.side_to_move()).first() {
+                            Some(&m) => m,
+                            None => return Continue(true)
+                        }
+                    }
+                };
+                game.make_move(m);
 
-                redraw(&grid, &board, &data);
+                redraw(&grid, game.board(), &data);
                 players_turn.set(true);
-                turn_start.set(None);
-
-                if board.is_checkmate(SIDE_PLAYER) {
-                    let dialog = Dialog::new_with_buttons(
-                        Some("Checkmate!"),
-                        Some(&window),
-                        DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
-                        &[
-                            ("Ok", ResponseType::Ok.into())
-                        ]
-                    );
-                    dialog.get_content_area().add(&Label::new("You lost!"));
-                    dialog.show_all();
-                    dialog.run();
-                    dialog.destroy();
-                }
-            } else {
-                if turn_start.get().map(|t| t.elapsed() >= Duration::from_secs(TIMEOUT)).unwrap_or(false) {
-                    exit.store(true, Ordering::SeqCst);
-                    turn_start.set(None);
+
+                if let Some(outcome) = game.outcome() {
+                    clock.borrow_mut().stop();
+                    show_outcome(&window, outcome);
+                } else {
+                    clock.borrow_mut().start(SIDE_PLAYER);
                 }
             }
             Continue(true)
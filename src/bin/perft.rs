@@ -0,0 +1,39 @@
+use chess_minimax::{
+    board::Board,
+    fen::STARTING_POSITION
+};
+use std::{env, process, time::Instant};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (depth, fen) = match args.split_first() {
+        Some((depth, fen)) => (depth.parse::<u8>().ok(), fen.join(" ")),
+        None => (None, String::new())
+    };
+    let depth = match depth {
+        Some(depth) if depth >= 1 => depth,
+        _ => {
+            eprintln!("usage: perft <depth> [fen]");
+            process::exit(1);
+        }
+    };
+    let fen = if fen.is_empty() { STARTING_POSITION } else { &fen };
+    let mut board = match Board::from_fen(fen) {
+        Ok(board) => board,
+        Err(err) => {
+            eprintln!("invalid fen: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let start = Instant::now();
+    let mut total = 0;
+    for (m, nodes) in board.divide(depth) {
+        println!("{}: {}", m, nodes);
+        total += nodes;
+    }
+    let elapsed = start.elapsed();
+    println!();
+    println!("nodes: {}", total);
+    println!("time: {} ms", elapsed.as_millis());
+}
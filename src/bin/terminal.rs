@@ -1,6 +1,9 @@
 use chess_minimax::{
     board::Board,
+    eval::Evaluation,
+    game::Game,
     terminal::Session,
+    transposition::TranspositionTable,
     Side
 };
 use std::{
@@ -16,10 +19,13 @@ fn main() -> Result<(), Error> {
 
     let mut s = Session {
         out: stdout,
-        board,
-        side: Side::Black,
-        undo: Vec::new(),
-        highlight: HashSet::new()
+        game: Game::new(board),
+        highlight: HashSet::new(),
+        orientation: Side::White,
+        table: TranspositionTable::default(),
+        clock: None,
+        evaluation: Evaluation::default(),
+        evaluator: Evaluation::default().evaluator()
     };
 
     let mut editor = Editor::<()>::new();
@@ -0,0 +1,269 @@
+use chess_minimax::{
+    board::{Board, Move},
+    clock,
+    eval::{Evaluation, Evaluator},
+    fen::STARTING_POSITION,
+    game::Game,
+    minimax::MATE,
+    search::{Iteration, Search, SearchLimits},
+    transposition::{self, TranspositionTable},
+    Side
+};
+use std::{
+    io::{self, BufRead},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex
+    },
+    thread,
+    time::Duration
+};
+
+const NAME: &str = "chess-minimax";
+const AUTHOR: &str = "jD91mZM2";
+const MAX_HASH: usize = 4096;
+const MAX_SKILL: u8 = 20;
+
+/// Format a score the way UCI expects it, either in centipawns or in moves
+/// until mate
+fn format_score(score: i32) -> String {
+    if score.abs() > MATE - 1000 {
+        let plies = MATE - score.abs();
+        let moves = (plies + 1) / 2;
+        format!("mate {}", if score > 0 { moves } else { -moves })
+    } else {
+        format!("cp {}", score)
+    }
+}
+
+fn print_info(iteration: &Iteration) {
+    let result = iteration.result;
+    let nodes = iteration.stats.nodes + iteration.stats.qnodes;
+    let millis = iteration.elapsed.as_millis() as u64;
+    let nps = nodes * 1000 / millis.max(1);
+    let pv: Vec<_> = result.pv.iter().map(Move::to_string).collect();
+    println!(
+        "info depth {} score {} nodes {} nps {} time {} pv {}",
+        result.depth,
+        format_score(result.score),
+        nodes,
+        nps,
+        millis,
+        pv.join(" ")
+    );
+}
+
+struct Engine {
+    game: Game,
+    table: Arc<Mutex<TranspositionTable>>,
+    skill: u8,
+    evaluation: Evaluation,
+    /// Kept between searches so caches like the pawn hash table are reused.
+    /// It's moved into the search thread while searching, and returned when
+    /// the thread is joined.
+    evaluator: Option<Box<dyn Evaluator + Send>>,
+    exit: Arc<AtomicBool>,
+    search: Option<thread::JoinHandle<Box<dyn Evaluator + Send>>>
+}
+impl Engine {
+    fn new() -> Self {
+        Self {
+            game: Game::default(),
+            table: Arc::new(Mutex::new(TranspositionTable::default())),
+            skill: MAX_SKILL,
+            evaluation: Evaluation::default(),
+            evaluator: Some(Evaluation::default().evaluator()),
+            exit: Arc::new(AtomicBool::new(false)),
+            search: None
+        }
+    }
+    /// Stop the running search, if any. It still reports its best move.
+    fn stop(&mut self) {
+        if let Some(search) = self.search.take() {
+            self.exit.store(true, Ordering::SeqCst);
+            self.evaluator = Some(search.join().unwrap());
+        }
+    }
+    fn set_option(&mut self, args: &[&str]) {
+        // setoption name <name, possibly with spaces> value <value>
+        if args.first() != Some(&"name") {
+            println!("info string expected setoption name <name> value <value>");
+            return;
+        }
+        let value_index = args.iter().position(|&arg| arg == "value");
+        let name = args[1..value_index.unwrap_or(args.len())].join(" ");
+        let value = value_index.and_then(|i| args.get(i + 1));
+
+        if name.eq_ignore_ascii_case("evaluation") {
+            match value.and_then(|value| Evaluation::from_name(value)) {
+                Some(evaluation) => {
+                    self.evaluation = evaluation;
+                    self.evaluator = Some(evaluation.evaluator());
+                },
+                None => println!("info string unknown evaluation: {}", args.join(" "))
+            }
+            return;
+        }
+        match (&*name.to_ascii_lowercase(), value.and_then(|value| value.parse::<usize>().ok())) {
+            ("hash", Some(megabytes)) => self.table.lock().unwrap().resize(megabytes.min(MAX_HASH)),
+            ("skill level", Some(skill)) => self.skill = skill.min(MAX_SKILL as usize) as u8,
+            _ => println!("info string unknown option or invalid value: {}", args.join(" "))
+        }
+    }
+    fn position(&mut self, args: &[&str]) {
+        let moves_index = args.iter().position(|&arg| arg == "moves").unwrap_or(args.len());
+        let board = match args.first() {
+            Some(&"startpos") => Board::from_fen(STARTING_POSITION),
+            Some(&"fen") => Board::from_fen(&args[1..moves_index].join(" ")),
+            _ => {
+                println!("info string expected startpos or fen");
+                return;
+            }
+        };
+        let board = match board {
+            Ok(board) => board,
+            Err(err) => {
+                println!("info string invalid fen: {}", err);
+                return;
+            }
+        };
+
+        let mut game = Game::new(board);
+        for s in args.iter().skip(moves_index + 1) {
+            match game.board().parse_long_algebraic(s) {
+                Ok(m) => game.make_move(m),
+                Err(err) => {
+                    println!("info string {}", err);
+                    return;
+                }
+            }
+        }
+        self.game = game;
+    }
+    fn go(&mut self, args: &[&str]) {
+        self.stop();
+
+        let side = self.game.board().side_to_move();
+        let mut limits = SearchLimits::default();
+        let (mut time, mut increment, mut moves_to_go) = (None, Duration::from_secs(0), None);
+
+        let mut args = args.iter();
+        while let Some(&arg) = args.next() {
+            let mut value = || args.next().and_then(|value| value.parse::<u64>().ok());
+            match arg {
+                "depth" => limits.depth = value().map(|depth| depth.min(u8::MAX as u64) as u8),
+                "nodes" => limits.nodes = value(),
+                "movetime" => limits.move_time = value().map(Duration::from_millis),
+                "wtime" | "btime" => {
+                    let value = value().map(Duration::from_millis);
+                    if (arg == "wtime") == (side == Side::White) {
+                        time = value;
+                    }
+                },
+                "winc" | "binc" => {
+                    let value = value().map(Duration::from_millis);
+                    if (arg == "winc") == (side == Side::White) {
+                        increment = value.unwrap_or(increment);
+                    }
+                },
+                "movestogo" => moves_to_go = value().map(|moves| moves as u32),
+                "infinite" => limits.infinite = true,
+                _ => ()
+            }
+        }
+        if let Some(time) = time {
+            let budget = clock::allocate(time, moves_to_go, increment);
+            limits.soft_time = Some(budget.soft);
+            limits.move_time = Some(limits.move_time.map(|t| t.min(budget.hard)).unwrap_or(budget.hard));
+        }
+        if self.skill < MAX_SKILL {
+            // A weaker engine simply doesn't look as far ahead
+            let depth = self.skill / 2 + 1;
+            limits.depth = Some(limits.depth.map(|d| d.min(depth)).unwrap_or(depth));
+        }
+
+        self.exit.store(false, Ordering::SeqCst);
+        let mut game = self.game.clone();
+        let table = Arc::clone(&self.table);
+        let exit = Arc::clone(&self.exit);
+        let evaluation = self.evaluation;
+        let mut evaluator = self.evaluator.take().unwrap_or_else(|| evaluation.evaluator());
+        self.search = Some(thread::spawn(move || {
+            let mut table = table.lock().unwrap();
+            let mut search = Search::new(limits, &mut table);
+            search.evaluator = Some(&mut *evaluator);
+            search.exit = Some(&exit);
+            let result = game.search(&mut search, print_info);
+
+            if limits.infinite {
+                // The best move may only be sent once the GUI asks for it
+                while !exit.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+            match result {
+                Some(result) => match result.pv.get(1) {
+                    Some(ponder) => println!("bestmove {} ponder {}", result.move_, ponder),
+                    None => println!("bestmove {}", result.move_)
+                },
+                None => {
+                    // A search that's stopped straight away has no move to
+                    // offer, but the GUI still needs a legal one
+                    let board = game.board();
+                    match board.legal_moves(board.side_to_move()).first() {
+                        Some(m) => println!("bestmove {}", m),
+                        None => println!("bestmove 0000")
+                    }
+                }
+            }
+            evaluator
+        }));
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut engine = Engine::new();
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        let args: Vec<_> = line.split_whitespace().collect();
+        let (cmd, args) = match args.split_first() {
+            Some((cmd, args)) => (*cmd, args),
+            None => continue
+        };
+        match cmd {
+            "uci" => {
+                println!("id name {}", NAME);
+                println!("id author {}", AUTHOR);
+                println!("option name Hash type spin default {} min 0 max {}", transposition::DEFAULT_SIZE, MAX_HASH);
+                println!("option name Skill Level type spin default {} min 0 max {}", MAX_SKILL, MAX_SKILL);
+                let vars: Vec<_> = Evaluation::ALL.iter().map(|evaluation| format!("var {}", evaluation)).collect();
+                println!("option name Evaluation type combo default {} {}", Evaluation::default(), vars.join(" "));
+                println!("uciok");
+            },
+            "isready" => println!("readyok"),
+            "setoption" => {
+                engine.stop();
+                engine.set_option(args);
+            },
+            "ucinewgame" => {
+                engine.stop();
+                engine.game = Game::default();
+                engine.table.lock().unwrap().clear();
+                engine.evaluator = Some(engine.evaluation.evaluator());
+            },
+            "position" => {
+                engine.stop();
+                engine.position(args);
+            },
+            "go" => engine.go(args),
+            "stop" => engine.stop(),
+            "quit" => break,
+            // Unknown commands are to be ignored
+            _ => ()
+        }
+    }
+    engine.stop();
+    Ok(())
+}
@@ -0,0 +1,339 @@
+use chess_minimax::{
+    board::{Board, Move},
+    clock,
+    game::{Game, GameOutcome},
+    minimax::{MinimaxResult, MATE},
+    search::{Iteration, Search, SearchLimits},
+    transposition::TranspositionTable,
+    Side
+};
+use std::{
+    io::{self, BufRead},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+        Arc, Mutex
+    },
+    thread,
+    time::Duration
+};
+
+const NAME: &str = "chess-minimax";
+
+/// Something the main loop needs to react to
+enum Event {
+    /// A line sent by the GUI
+    Command(String),
+    /// A search finished, along with which search it was
+    Done(u32, Option<MinimaxResult>)
+}
+
+/// Parse a duration in seconds, optionally written as `minutes:seconds`
+fn parse_seconds(s: &str) -> Option<Duration> {
+    let (minutes, seconds) = match s.find(':') {
+        Some(i) => (s[..i].parse::<u64>().ok()?, s[i+1..].parse::<f64>().ok()?),
+        None => (0, s.parse::<f64>().ok()?)
+    };
+    if !seconds.is_finite() || seconds < 0.0 {
+        return None;
+    }
+    Some(Duration::from_secs(minutes * 60) + Duration::from_millis((seconds * 1000.0) as u64))
+}
+
+/// Format a score the way CECP expects it, in centipawns or as 100000 plus
+/// the number of moves until mate
+fn format_score(score: i32) -> i32 {
+    if score.abs() > MATE - 1000 {
+        let moves = (MATE - score.abs() + 1) / 2;
+        if score > 0 { MATE + moves } else { -MATE - moves }
+    } else {
+        score
+    }
+}
+
+fn print_thinking(iteration: &Iteration) {
+    let result = iteration.result;
+    let pv: Vec<_> = result.pv.iter().map(Move::to_string).collect();
+    println!(
+        "{} {} {} {} {}",
+        result.depth,
+        format_score(result.score),
+        iteration.elapsed.as_millis() / 10,
+        iteration.stats.nodes + iteration.stats.qnodes,
+        pv.join(" ")
+    );
+}
+
+/// Format the result command announcing how the game ended
+fn format_result(outcome: GameOutcome) -> String {
+    let result = match outcome.winner() {
+        Some(Side::White) => "1-0",
+        Some(Side::Black) => "0-1",
+        None => "1/2-1/2"
+    };
+    format!("{} {{{}}}", result, outcome)
+}
+
+struct Engine {
+    game: Game,
+    table: Arc<Mutex<TranspositionTable>>,
+    /// The side the engine plays, or `None` in force mode
+    side: Option<Side>,
+    post: bool,
+    depth: Option<u8>,
+    move_time: Option<Duration>,
+    /// Moves per time control period (0 if it's the whole game) and the
+    /// increment, as set by `level`
+    level: Option<(u32, Duration)>,
+    /// The engine's remaining time, as set by `level` and `time`
+    time: Option<Duration>,
+    exit: Arc<AtomicBool>,
+    search: Option<thread::JoinHandle<()>>,
+    /// Identifies the latest search, so results of stopped ones are ignored
+    generation: u32,
+    events: Sender<Event>
+}
+impl Engine {
+    fn new(events: Sender<Event>) -> Self {
+        Self {
+            game: Game::default(),
+            table: Arc::new(Mutex::new(TranspositionTable::default())),
+            side: Some(Side::Black),
+            post: false,
+            depth: None,
+            move_time: None,
+            level: None,
+            time: None,
+            exit: Arc::new(AtomicBool::new(false)),
+            search: None,
+            generation: 0,
+            events
+        }
+    }
+    /// Stop the running search, if any, and throw its result away
+    fn stop(&mut self) {
+        if let Some(search) = self.search.take() {
+            self.exit.store(true, Ordering::SeqCst);
+            search.join().unwrap();
+        }
+    }
+    /// Print the result if the game has ended. Returns true if it has.
+    fn check_outcome(&mut self) -> bool {
+        match self.game.outcome() {
+            Some(outcome) => {
+                println!("{}", format_result(outcome));
+                true
+            },
+            None => false
+        }
+    }
+    fn limits(&self) -> SearchLimits {
+        let mut limits = SearchLimits::default();
+        if let Some(time) = self.move_time {
+            limits.move_time = Some(time);
+        } else if let Some(time) = self.time {
+            let (moves, increment) = self.level.unwrap_or((0, Duration::from_secs(0)));
+            let moves_to_go = if moves == 0 {
+                None
+            } else {
+                let played = u32::from(self.game.board().fullmove_number()) - 1;
+                Some(moves - played % moves)
+            };
+            let budget = clock::allocate(time, moves_to_go, increment);
+            limits = budget.limits();
+        }
+        limits.depth = self.depth;
+        limits
+    }
+    /// Start thinking if it's the engine's turn
+    fn think(&mut self) {
+        if self.search.is_some() || self.side != Some(self.game.board().side_to_move()) {
+            return;
+        }
+        if self.game.outcome().is_some() {
+            return;
+        }
+
+        self.generation += 1;
+        self.exit.store(false, Ordering::SeqCst);
+        let limits = self.limits();
+        let generation = self.generation;
+        let post = self.post;
+        let mut game = self.game.clone();
+        let table = Arc::clone(&self.table);
+        let exit = Arc::clone(&self.exit);
+        let events = self.events.clone();
+        self.search = Some(thread::spawn(move || {
+            let mut table = table.lock().unwrap();
+            let mut search = Search::new(limits, &mut table);
+            search.exit = Some(&exit);
+            let result = game.search(&mut search, |iteration| if post {
+                print_thinking(iteration);
+            });
+            let _ = events.send(Event::Done(generation, result));
+        }));
+    }
+    /// Play the move a search came up with
+    fn done(&mut self, generation: u32, result: Option<MinimaxResult>) {
+        if generation != self.generation {
+            return;
+        }
+        let search = match self.search.take() {
+            Some(search) => search,
+            None => return
+        };
+        search.join().unwrap();
+
+        // A search that's interrupted straight away has no move to offer
+        let m = match result {
+            Some(result) => Some(result.move_),
+            None => {
+                let board = self.game.board();
+                board.legal_moves(board.side_to_move()).first().cloned()
+            }
+        };
+        if let Some(m) = m {
+            println!("move {}", m);
+            self.game.make_move(m);
+        }
+        self.check_outcome();
+    }
+    fn user_move(&mut self, s: &str) {
+        match self.game.board().parse_move(s) {
+            Ok(m) => {
+                // Whatever the engine was thinking about no longer applies
+                self.stop();
+                self.game.make_move(m);
+                if !self.check_outcome() {
+                    self.think();
+                }
+            },
+            Err(err) => println!("Illegal move ({}): {}", err, s)
+        }
+    }
+    /// Run one command. Returns false if the engine should quit.
+    fn command(&mut self, line: &str) -> bool {
+        let args: Vec<_> = line.split_whitespace().collect();
+        let (cmd, args) = match args.split_first() {
+            Some((cmd, args)) => (*cmd, args),
+            None => return true
+        };
+        match cmd {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "name" | "rating" => (),
+            "protover" => {
+                println!(
+                    "feature myname=\"{}\" usermove=1 setboard=1 ping=1 playother=1 colors=0 \
+                     analyze=0 sigint=0 sigterm=0 reuse=1 done=1",
+                    NAME
+                );
+            },
+            "new" => {
+                self.stop();
+                self.game = Game::default();
+                self.side = Some(Side::Black);
+                self.depth = None;
+                self.table.lock().unwrap().clear();
+            },
+            "force" | "result" => {
+                self.stop();
+                self.side = None;
+            },
+            "go" => {
+                self.stop();
+                self.side = Some(self.game.board().side_to_move());
+                self.think();
+            },
+            "playother" => {
+                self.stop();
+                self.side = Some(!self.game.board().side_to_move());
+            },
+            "usermove" => match args.first() {
+                Some(s) => self.user_move(s),
+                None => println!("Error (missing move): {}", line)
+            },
+            "?" => self.exit.store(true, Ordering::SeqCst),
+            "undo" => {
+                self.stop();
+                self.game.undo();
+            },
+            "remove" => {
+                self.stop();
+                self.game.undo();
+                self.game.undo();
+            },
+            "setboard" => {
+                self.stop();
+                match Board::from_fen(&args.join(" ")) {
+                    Ok(board) => self.game = Game::new(board),
+                    Err(err) => println!("tellusererror Illegal position: {}", err)
+                }
+            },
+            "level" => {
+                let moves = args.first().and_then(|s| s.parse().ok());
+                // The base time is in minutes, unless written like `0:30`
+                let base = args.get(1).and_then(|s| match s.find(':') {
+                    Some(_) => parse_seconds(s),
+                    None => s.parse::<u64>().ok().map(|minutes| Duration::from_secs(minutes * 60))
+                });
+                let increment = args.get(2).and_then(|s| parse_seconds(s));
+                match (moves, base, increment) {
+                    (Some(moves), Some(base), Some(increment)) => {
+                        self.level = Some((moves, increment));
+                        self.time = Some(base);
+                        self.move_time = None;
+                    },
+                    _ => println!("Error (invalid level): {}", line)
+                }
+            },
+            "st" => match args.first().and_then(|s| parse_seconds(s)) {
+                Some(time) => self.move_time = Some(time),
+                None => println!("Error (invalid time): {}", line)
+            },
+            "sd" => match args.first().and_then(|s| s.parse::<u8>().ok()) {
+                Some(depth) => self.depth = Some(depth),
+                None => println!("Error (invalid depth): {}", line)
+            },
+            "time" => match args.first().and_then(|s| s.parse::<u64>().ok()) {
+                Some(centiseconds) => self.time = Some(Duration::from_millis(centiseconds * 10)),
+                None => println!("Error (invalid time): {}", line)
+            },
+            "otim" => (),
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "ping" => println!("pong {}", args.first().unwrap_or(&"")),
+            "quit" => return false,
+            _ => println!("Error (unknown command): {}", cmd)
+        }
+        true
+    }
+}
+
+fn main() {
+    let (sender, receiver) = mpsc::channel();
+
+    let stdin_sender = sender.clone();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break
+            };
+            if stdin_sender.send(Event::Command(line)).is_err() {
+                return;
+            }
+        }
+        let _ = stdin_sender.send(Event::Command(String::from("quit")));
+    });
+
+    let mut engine = Engine::new(sender);
+    for event in receiver {
+        match event {
+            Event::Command(line) => if !engine.command(&line) {
+                break;
+            },
+            Event::Done(generation, result) => engine.done(generation, result)
+        }
+    }
+    engine.stop();
+}
@@ -2,25 +2,25 @@ use arrayvec::ArrayVec;
 use crate::{
     piece::{Piece, PieceKind},
     Pos,
+    zobrist,
     Side,
 };
-use std::{
-    collections::HashSet,
-    mem
-};
+use std::{collections::HashSet, fmt};
 
-pub type Change = ArrayVec<[Undo; 6]>;
+pub type Change = ArrayVec<[Undo; 8]>;
 
 /// Describes how to revert a change to the board. Used for undoing moves.
 #[derive(Clone, Debug)]
 pub enum Undo {
     Set(Pos, Option<Piece>),
     EnPassant(Option<Pos>),
-    Castling(Side, Castling)
+    Castling(Side, Castling),
+    SideToMove(Side),
+    Clocks(u16, u16)
 }
 
 /// Information about whether or not one side can do a castling
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Castling {
     pub queenside: bool,
     pub kingside: bool
@@ -43,6 +43,76 @@ pub(crate) fn edge_offset(side: Side, y: i8) -> i8 {
         Side::White => (WIDTH - 1) - y
     }
 }
+/// Return the direction a pawn of the specified side moves in
+pub(crate) fn forward(side: Side) -> Pos {
+    match side {
+        Side::Black => Pos(0, 1),
+        Side::White => Pos(0, -1)
+    }
+}
+/// Extra information about a move. Flags can be combined using `|`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MoveFlags(u8);
+impl MoveFlags {
+    /// The move captures a piece
+    pub const CAPTURE: Self = MoveFlags(1);
+    /// The move is a pawn capturing en passant
+    pub const EN_PASSANT: Self = MoveFlags(1 << 1);
+    /// The move is a pawn jumping two squares from its starting position
+    pub const DOUBLE_PUSH: Self = MoveFlags(1 << 2);
+    /// The move is a king castling, the rook is moved as well
+    pub const CASTLING: Self = MoveFlags(1 << 3);
+
+    /// Returns true if all the flags in `other` are set
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+impl std::ops::BitOr for MoveFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self::Output {
+        MoveFlags(self.0 | other.0)
+    }
+}
+impl std::ops::BitOrAssign for MoveFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+/// A move of a piece from one position to another
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub from: Pos,
+    pub to: Pos,
+    /// What a pawn reaching the other edge turns into
+    pub promotion: Option<PieceKind>,
+    pub flags: MoveFlags
+}
+impl Move {
+    /// Returns true if this move captures a piece
+    pub fn is_capture(self) -> bool {
+        self.flags.contains(MoveFlags::CAPTURE)
+    }
+    /// Returns true if this move is a castling
+    pub fn is_castling(self) -> bool {
+        self.flags.contains(MoveFlags::CASTLING)
+    }
+}
+/// Formats the move in long algebraic notation, like `e2e4` or `e7e8q`
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &Pos(x, y) in &[self.from, self.to] {
+            write!(f, "{}{}", (b'a' + x as u8) as char, WIDTH - y)?;
+        }
+        if let Some(kind) = self.promotion {
+            write!(f, "{}", kind.letter().to_ascii_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
 pub(crate) fn is_castling(piece: Piece, m: Pos) -> bool {
     let Pos(rel_x, _) = m;
     piece.kind == PieceKind::King && rel_x.abs() == 2
@@ -54,7 +124,12 @@ pub struct Board {
     pub(crate) pieces: [[Option<Piece>; WIDTH as usize]; WIDTH as usize],
     pub(crate) en_passant: Option<Pos>,
     pub(crate) castling_black: Castling,
-    pub(crate) castling_white: Castling
+    pub(crate) castling_white: Castling,
+    pub(crate) side_to_move: Side,
+    pub(crate) halfmove_clock: u16,
+    pub(crate) fullmove_number: u16,
+    pub(crate) zobrist: u64,
+    pub(crate) pawn_zobrist: u64
 }
 impl Default for Board {
     fn default() -> Self {
@@ -69,7 +144,7 @@ impl Default for Board {
             Some(Piece::white(kind))
         }
 
-        Self {
+        let mut board = Self {
             pieces: [
 //[white(King), None,          None,          None,         None,        None,          None,          None],
 //[None,        None,          None,          None,         None,        None,          None,          None],
@@ -90,8 +165,16 @@ impl Default for Board {
             ],
             en_passant: None,
             castling_black: Castling::default(),
-            castling_white: Castling::default()
-        }
+            castling_white: Castling::default(),
+            side_to_move: Side::White,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            zobrist: 0,
+            pawn_zobrist: 0
+        };
+        board.zobrist = board.compute_zobrist();
+        board.pawn_zobrist = board.compute_pawn_key();
+        board
     }
 }
 impl Board {
@@ -106,12 +189,21 @@ impl Board {
         let Pos(x, y) = pos;
         self.pieces[y as usize][x as usize]
     }
-    /// Get a mutable reference to the piece at the requested position
-    pub fn get_mut(&mut self, pos: Pos) -> &mut Option<Piece> {
+    /// Replace the piece at the requested position, returning the old one.
+    /// This keeps the position key up to date.
+    pub fn set(&mut self, pos: Pos, piece: Option<Piece>) -> Option<Piece> {
         assert!(pos.is_valid());
 
         let Pos(x, y) = pos;
-        &mut self.pieces[y as usize][x as usize]
+        let old = std::mem::replace(&mut self.pieces[y as usize][x as usize], piece);
+        for piece in old.iter().chain(piece.iter()) {
+            let key = zobrist::piece(pos, *piece);
+            self.zobrist ^= key;
+            if piece.kind == PieceKind::Pawn {
+                self.pawn_zobrist ^= key;
+            }
+        }
+        old
     }
 
     /// Return a reference over all the rows, starting at 8 going down to 1
@@ -123,17 +215,112 @@ impl Board {
         self.pieces.iter()
     }
 
-    fn castling(&mut self, side: Side) -> &mut Castling {
+    /// Return the side whose turn it is
+    pub fn side_to_move(&self) -> Side {
+        self.side_to_move
+    }
+    /// Return the number of halfmoves since the last capture or pawn move
+    pub fn halfmove_clock(&self) -> u16 {
+        self.halfmove_clock
+    }
+    /// Return the number of the current full move, starting at 1 and
+    /// incremented after each black move
+    pub fn fullmove_number(&self) -> u16 {
+        self.fullmove_number
+    }
+    /// Return the castling rights of the specified side
+    pub fn castling_rights(&self, side: Side) -> Castling {
         match side {
+            Side::Black => self.castling_black,
+            Side::White => self.castling_white
+        }
+    }
+    /// Return the position of the pawn that can currently be captured en
+    /// passant, if any. Note that this is the position of the pawn itself, not
+    /// the square it skipped.
+    pub fn en_passant(&self) -> Option<Pos> {
+        self.en_passant
+    }
+
+    /// Return the Zobrist key of this position: the pieces, the side to move,
+    /// castling rights and en passant. Equal positions have equal keys. The
+    /// key is updated incrementally on every change, so this is free.
+    pub fn position_key(&self) -> u64 {
+        self.zobrist
+    }
+    /// Return the Zobrist key of only the pawns on this board, which is what
+    /// pawn structure depends on. Also updated incrementally.
+    pub fn pawn_key(&self) -> u64 {
+        self.pawn_zobrist
+    }
+
+    /// Return whether an enemy pawn stands next to the pawn on `pos`, so it
+    /// could be captured en passant. Only then is the en passant square kept
+    /// track of, otherwise the same position would end up with two keys.
+    pub(crate) fn can_capture_en_passant(&self, pos: Pos) -> bool {
+        let enemy = match self.get(pos) {
+            Some(Piece { kind: PieceKind::Pawn, side }) => Some(Piece { kind: PieceKind::Pawn, side: !side }),
+            _ => return false
+        };
+        [Pos(-1, 0), Pos(1, 0)].iter()
+            .map(|&dx| pos + dx)
+            .any(|pos| pos.is_valid() && self.get(pos) == enemy)
+    }
+    /// Drop castling rights without the king and rook on their starting
+    /// squares, and an en passant pawn nobody can capture. Neither could ever
+    /// be used, and keeping them would give the same position two keys. The
+    /// keys are not updated, this is meant for boards that are being built.
+    pub(crate) fn drop_unusable_rights(&mut self) {
+        for &side in &[Side::White, Side::Black] {
+            let row = edge_offset(side, 0);
+            let is = |x: i8, kind| self.get(Pos(x, row)) == Some(Piece { kind, side });
+            let king = is(4, PieceKind::King);
+            let castling = self.castling_rights(side);
+            let usable = Castling {
+                queenside: castling.queenside && king && is(0, PieceKind::Rook),
+                kingside: castling.kingside && king && is(WIDTH - 1, PieceKind::Rook)
+            };
+            match side {
+                Side::Black => self.castling_black = usable,
+                Side::White => self.castling_white = usable
+            }
+        }
+        if let Some(pawn) = self.en_passant {
+            let side = !self.side_to_move;
+            if pawn.1 != edge_offset(side, 3)
+                    || self.get(pawn).map(|p| p.side) != Some(side)
+                    || !self.can_capture_en_passant(pawn) {
+                self.en_passant = None;
+            }
+        }
+    }
+    fn set_en_passant(&mut self, en_passant: Option<Pos>) {
+        self.zobrist ^= zobrist::en_passant(self.en_passant) ^ zobrist::en_passant(en_passant);
+        self.en_passant = en_passant;
+    }
+    fn set_castling(&mut self, side: Side, castling: Castling) {
+        let old = match side {
             Side::Black => &mut self.castling_black,
             Side::White => &mut self.castling_white
-        }
+        };
+        self.zobrist ^= zobrist::castling(side, *old) ^ zobrist::castling(side, castling);
+        *old = castling;
+    }
+    fn set_side_to_move(&mut self, side: Side) {
+        self.zobrist ^= zobrist::side_to_move(self.side_to_move) ^ zobrist::side_to_move(side);
+        self.side_to_move = side;
+    }
+    /// Panics in debug builds if the incrementally updated key has drifted
+    /// from the board
+    fn verify_zobrist(&self) {
+        debug_assert_eq!(self.zobrist, self.compute_zobrist(), "incremental zobrist key is out of sync");
+        debug_assert_eq!(self.pawn_zobrist, self.compute_pawn_key(), "incremental pawn key is out of sync");
     }
 
     /// Does extra validation for a move.
     /// Returns yes if the piece at `from` make the move `m`.
     /// Warning: This may return false positives if called on anything other than the results of `piece.moves()`!
-    pub fn can_move(&mut self, from: Pos, m: Pos) -> bool {
+    pub fn can_move(&self, from: Pos, m: Pos) -> bool {
         let Pos(from_x, from_y) = from;
         let Pos(rel_x, rel_y) = m;
         let dest = from + m;
@@ -164,17 +351,17 @@ impl Board {
                 }
                 // Castling
                 let row = edge_offset(piece.side, 0);
-                if from_y != row {
+                if from != Pos(4, row) {
                     return false;
                 }
                 let empty = if rel_x < 0 {
-                    self.castling(piece.side).queenside
+                    self.castling_rights(piece.side).queenside
                         && self.get(Pos(0, row)) == Some(Piece { kind: PieceKind::Rook, side: piece.side })
                         && self.get(Pos(1, row)).is_none()
                         && self.get(Pos(2, row)).is_none()
                         && self.get(Pos(3, row)).is_none()
                 } else {
-                    self.castling(piece.side).kingside
+                    self.castling_rights(piece.side).kingside
                         && self.get(Pos(WIDTH-1, row)) == Some(Piece { kind: PieceKind::Rook, side: piece.side })
                         && self.get(Pos(WIDTH-2, row)).is_none()
                         && self.get(Pos(WIDTH-3, row)).is_none()
@@ -182,9 +369,8 @@ impl Board {
                 if !empty {
                     return false;
                 }
-                let threatened = self.threatens(!piece.side);
                 for i in 0..3 {
-                    if threatened.contains(&Pos(from_x + rel_x.signum() * i, row)) {
+                    if self.is_attacked(Pos(from_x + rel_x.signum() * i, row), !piece.side) {
                         return false;
                     }
                 }
@@ -221,85 +407,127 @@ impl Board {
             repeat,
 
             repeat_cursor: None,
-            i: 0
+            i: 0,
+
+            promotions: ArrayVec::new()
         }
     }
 
-    /// Move a piece, replacing whatever was already on `to`. Handles any logic
-    /// like spawning a queen. Can be undone.
-    pub fn move_(&mut self, from: Pos, to: Pos) -> Change {
-        let prev_en_passant = self.en_passant.take();
+    /// Build a `Move` from `from` to `to`, deriving its flags from the
+    /// current board. A pawn reaching the other edge is promoted to
+    /// `promotion`, or a queen if none was specified.
+    /// Note: This doesn't check if the move is actually possible.
+    pub fn infer_move(&self, from: Pos, to: Pos, promotion: Option<PieceKind>) -> Move {
+        let mut m = Move {
+            from,
+            to,
+            promotion: None,
+            flags: MoveFlags::default()
+        };
+        if self.get(to).is_some() {
+            m.flags |= MoveFlags::CAPTURE;
+        }
+        if let Some(piece) = self.get(from) {
+            let Pos(from_x, from_y) = from;
+            let Pos(to_x, to_y) = to;
+            match piece.kind {
+                PieceKind::Pawn => {
+                    if from_x != to_x && self.get(to).is_none() && self.en_passant == Some(Pos(to_x, from_y)) {
+                        m.flags |= MoveFlags::CAPTURE | MoveFlags::EN_PASSANT;
+                    }
+                    if (to_y - from_y).abs() == 2 {
+                        m.flags |= MoveFlags::DOUBLE_PUSH;
+                    }
+                    if to_y == edge_offset(!piece.side, 0) {
+                        m.promotion = Some(promotion.unwrap_or(PieceKind::Queen));
+                    }
+                },
+                PieceKind::King if (to_x - from_x).abs() == 2 => {
+                    m.flags |= MoveFlags::CASTLING;
+                },
+                _ => ()
+            }
+        }
+        m
+    }
+    /// Make a move, replacing whatever was already on `m.to`. Handles any
+    /// logic like promoting pawns, and passes the turn to the other side. Can
+    /// be undone.
+    pub fn move_(&mut self, m: Move) -> Change {
+        let Move { from, to, .. } = m;
+        let prev_en_passant = self.en_passant;
+        self.set_en_passant(None);
 
         let mut vec = ArrayVec::new();
 
-        let piece = self.get_mut(from).take();
-        let old = mem::replace(self.get_mut(to), piece);
+        let piece = self.set(from, None);
+        let old = self.set(to, piece);
 
         vec.extend(ArrayVec::from([
             Undo::Set(from, piece),
             Undo::Set(to, old)
         ]));
 
+        if let Some(Piece { kind: PieceKind::Rook, side }) = old {
+            // A captured rook can no longer take part in a castling
+            let row = edge_offset(side, 0);
+            let castling = self.castling_rights(side);
+
+            if to == Pos(0, row) && castling.queenside {
+                vec.push(Undo::Castling(side, castling));
+                self.set_castling(side, Castling { queenside: false, ..castling });
+            } else if to == Pos(WIDTH-1, row) && castling.kingside {
+                vec.push(Undo::Castling(side, castling));
+                self.set_castling(side, Castling { kingside: false, ..castling });
+            }
+        }
+
         if let Some(piece) = piece {
             let Pos(from_x, from_y) = from;
-            let Pos(to_x, to_y) = to;
+            let Pos(to_x, _) = to;
             match piece.kind {
                 PieceKind::Pawn => {
-                    let en_passant = Pos(to_x, from_y);
-                    if to_y == edge_offset(!piece.side, 0) {
+                    if let Some(kind) = m.promotion {
                         // Pawn moved all the way to the other's edge, let's upgrade it!
-                        self.get_mut(to)
-                            .as_mut()
-                            .unwrap()
-                            .kind = PieceKind::Queen;
-                    } else if from_y == edge_offset(piece.side, 1) && to_y == edge_offset(piece.side, 3) {
+                        self.set(to, Some(Piece { kind, ..piece }));
+                    } else if m.flags.contains(MoveFlags::DOUBLE_PUSH) {
                         // Did initial move, is subject to en passant
-                        self.en_passant = Some(to);
-                    } else if from_x != to_x && prev_en_passant == Some(en_passant) {
+                        if self.can_capture_en_passant(to) {
+                            self.set_en_passant(Some(to));
+                        }
+                    } else if m.flags.contains(MoveFlags::EN_PASSANT) {
                         // Did en passant, kill victim
-                        let killed = self.get_mut(en_passant).take();
+                        let en_passant = Pos(to_x, from_y);
+                        let killed = self.set(en_passant, None);
                         vec.push(Undo::Set(en_passant, killed));
                     }
                 },
                 PieceKind::Rook => {
                     // Can no longer do a castling
                     let row = edge_offset(piece.side, 0);
-                    let castling = self.castling(piece.side);
+                    let castling = self.castling_rights(piece.side);
 
                     if from == Pos(0, row) {
                         if castling.queenside {
-                            vec.push(Undo::Castling(piece.side, *castling));
-                            castling.queenside = false;
+                            vec.push(Undo::Castling(piece.side, castling));
+                            self.set_castling(piece.side, Castling { queenside: false, ..castling });
                         }
                     } else if from == Pos(WIDTH-1, row) {
                         if castling.kingside {
-                            vec.push(Undo::Castling(piece.side, *castling));
-                            castling.kingside = false;
+                            vec.push(Undo::Castling(piece.side, castling));
+                            self.set_castling(piece.side, Castling { kingside: false, ..castling });
                         }
                     }
                 },
                 PieceKind::King => {
-                    let castling = *self.castling(piece.side);
-
-                    // Was this a castling?
-                    let row = edge_offset(piece.side, 0);
-
-                    let positions = if to_x - from_x == -2 && castling.queenside {
-                        Some((
-                            Pos(0, row),
-                            Pos(to_x + 1, row)
-                        ))
-                    } else if to_x - from_x == 2 && castling.kingside {
-                        Some((
-                            Pos(WIDTH-1, row),
-                            Pos(to_x - 1, row)
-                        ))
-                    } else {
-                        None
-                    };
-                    if let Some((rook_from, rook_to)) = positions {
-                        let piece = self.get_mut(rook_from).take();
-                        *self.get_mut(rook_to) = piece;
+                    if m.flags.contains(MoveFlags::CASTLING) {
+                        let (rook_from, rook_to) = if to_x < from_x {
+                            (Pos(0, from_y), Pos(to_x + 1, from_y))
+                        } else {
+                            (Pos(WIDTH-1, from_y), Pos(to_x - 1, from_y))
+                        };
+                        let piece = self.set(rook_from, None);
+                        self.set(rook_to, piece);
 
                         vec.extend(ArrayVec::from([
                             Undo::Set(rook_from, piece),
@@ -308,40 +536,73 @@ impl Board {
                     }
 
                     // You can no longer do a castling
+                    let castling = self.castling_rights(piece.side);
                     if castling.kingside || castling.queenside {
-                        let castling = self.castling(piece.side); // can't use mutable reference above
-
-                        vec.push(Undo::Castling(piece.side, *castling));
-                        *castling = Castling {
+                        vec.push(Undo::Castling(piece.side, castling));
+                        self.set_castling(piece.side, Castling {
                             queenside: false,
                             kingside: false
-                        };
+                        });
                     }
                 },
                 _ => ()
             }
+        }
 
-            if prev_en_passant.is_some() || self.en_passant.is_some() {
-                vec.push(Undo::EnPassant(prev_en_passant));
-            }
+        if prev_en_passant.is_some() || self.en_passant.is_some() {
+            vec.push(Undo::EnPassant(prev_en_passant));
+        }
+
+        vec.extend(ArrayVec::from([
+            Undo::Clocks(self.halfmove_clock, self.fullmove_number),
+            Undo::SideToMove(self.side_to_move)
+        ]));
+        if m.is_capture() || piece.map(|p| p.kind == PieceKind::Pawn).unwrap_or(false) {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock = self.halfmove_clock.saturating_add(1);
+        }
+        if self.side_to_move == Side::Black {
+            self.fullmove_number = self.fullmove_number.saturating_add(1);
+        }
+        self.set_side_to_move(!self.side_to_move);
+
+        self.verify_zobrist();
+        vec
+    }
+    /// Pass the turn to the other side without moving anything, also known as
+    /// a null move. Can be undone.
+    pub fn pass_turn(&mut self) -> Change {
+        let mut vec = ArrayVec::new();
+        if let Some(en_passant) = self.en_passant {
+            vec.push(Undo::EnPassant(Some(en_passant)));
+            self.set_en_passant(None);
         }
+        vec.push(Undo::SideToMove(self.side_to_move));
+        self.set_side_to_move(!self.side_to_move);
 
+        self.verify_zobrist();
         vec
     }
     /// Undo a move
     pub fn undo(&mut self, change: Change) {
         for undo in change {
             match undo {
-                Undo::Set(pos, piece) => *self.get_mut(pos) = piece,
-                Undo::EnPassant(pos) => self.en_passant = pos,
-                Undo::Castling(side, castling) => *self.castling(side) = castling
+                Undo::Set(pos, piece) => { self.set(pos, piece); },
+                Undo::EnPassant(pos) => self.set_en_passant(pos),
+                Undo::Castling(side, castling) => self.set_castling(side, castling),
+                Undo::SideToMove(side) => self.set_side_to_move(side),
+                Undo::Clocks(halfmove, fullmove) => {
+                    self.halfmove_clock = halfmove;
+                    self.fullmove_number = fullmove;
+                }
             }
         }
     }
 
     /// Return all tiles that are threatened (even if no piece is there) by the
     /// specified side
-    pub fn threatens(&mut self, side: Side) -> HashSet<Pos> {
+    pub fn threatens(&self, side: Side) -> HashSet<Pos> {
         let mut set = HashSet::new();
         let mut pieces = self.pieces(side);
         while let Some((from, piece)) = pieces.next(self) {
@@ -349,44 +610,26 @@ impl Board {
             // Castling detects if threatened, threatened can't check if castling is possible
             let mut moves = self.moves_for_filter(from, |m| !is_castling(piece, m));
 
-            while let Some(to) = moves.next(self) {
-                set.insert(to);
+            while let Some(m) = moves.next(self) {
+                set.insert(m.to);
             }
         }
         set
     }
     /// Return whatever piece is threatening the specified side's king, if any
-    pub fn check(&mut self, side: Side) -> Option<Pos> {
-        let mut pieces = self.pieces(!side);
-        while let Some((from, piece)) = pieces.next(self) {
-            // Castling can't consume any pieces
-            let mut moves = self.moves_for_filter(from, |m| !is_castling(piece, m));
-
-            while let Some(to) = moves.next(self) {
-                if self.get(to) == Some(Piece { kind: PieceKind::King, side }) {
-                    return Some(from);
-                }
-            }
-        }
-
-        None
+    pub fn check(&self, side: Side) -> Option<Pos> {
+        let king = self.king(side)?;
+        self.attackers(king, !side).first().cloned()
     }
-    /// Returns true if the specified side cannot make a move that's not in check
-    pub fn is_checkmate(&mut self, side: Side) -> bool {
-        let mut pieces = self.pieces(side);
-        while let Some((from, _)) = pieces.next(self) {
-            let mut moves = self.moves_for(from);
-            while let Some(to) = moves.next(self) {
-                let undo = self.move_(from, to);
-                let check = self.check(side);
-                self.undo(undo);
-
-                if check.is_none() {
-                    return false;
-                }
-            }
-        }
-        true
+    /// Returns true if the specified side is in check and cannot make a move
+    /// that gets it out of check
+    pub fn is_checkmate(&self, side: Side) -> bool {
+        self.check(side).is_some() && self.legal_moves(side).is_empty()
+    }
+    /// Returns true if the specified side is not in check, but cannot make a
+    /// move without placing itself in check
+    pub fn is_stalemate(&self, side: Side) -> bool {
+        self.check(side).is_none() && self.legal_moves(side).is_empty()
     }
 }
 impl<'a> IntoIterator for &'a Board {
@@ -443,13 +686,29 @@ pub struct MoveIter<F>
     repeat: bool,
 
     repeat_cursor: Option<(Pos, Pos)>,
-    i: usize
+    i: usize,
+
+    promotions: ArrayVec<[Move; 3]>
 }
 impl<F> MoveIter<F>
     where F: FnMut(Pos) -> bool
 {
-    /// Gets the next destination for a move in the "iterator"
-    pub fn next(&mut self, board: &mut Board) -> Option<Pos> {
+    /// Gets the next move in the "iterator". A pawn reaching the other edge
+    /// yields one move for each piece it can be promoted to.
+    pub fn next(&mut self, board: &Board) -> Option<Move> {
+        if let Some(m) = self.promotions.pop() {
+            return Some(m);
+        }
+        let to = self.next_target(board)?;
+        let m = board.infer_move(self.start, to, None);
+        if m.promotion.is_some() {
+            for &kind in &[PieceKind::Bishop, PieceKind::Rook, PieceKind::Knight] {
+                self.promotions.push(Move { promotion: Some(kind), ..m });
+            }
+        }
+        Some(m)
+    }
+    fn next_target(&mut self, board: &Board) -> Option<Pos> {
         if let Some((velocity, ref mut m)) = self.repeat_cursor {
             *m += velocity;
             if board.can_move(self.start, *m) {
@@ -0,0 +1,484 @@
+use crate::{
+    search::SearchLimits,
+    Side
+};
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant}
+};
+
+/// Moves a game is assumed to still last when the time control doesn't say
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+/// Time kept in reserve for the overhead of actually making a move
+const MAX_OVERHEAD: Duration = Duration::from_millis(50);
+
+/// How much time each side gets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeControl {
+    /// A fixed amount of time for the whole game
+    SuddenDeath { time: Duration },
+    /// Fischer: time is added after every move
+    Increment { time: Duration, increment: Duration },
+    /// Bronstein: the time used for a move is given back, up to `delay`
+    Delay { time: Duration, delay: Duration },
+    /// A fixed amount of time for every `moves` moves, unused time carries over
+    MovesPerPeriod { moves: u32, time: Duration }
+}
+impl TimeControl {
+    /// The time each side starts with
+    pub fn initial(self) -> Duration {
+        match self {
+            TimeControl::SuddenDeath { time }
+            | TimeControl::Increment { time, .. }
+            | TimeControl::Delay { time, .. }
+            | TimeControl::MovesPerPeriod { time, .. } => time
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum ParseTimeControlError {
+    #[fail(display = "invalid time control, expected something like 5, 5+3, 5d3 or 40/90")]
+    InvalidFormat,
+    #[fail(display = "invalid number: {:?}", _0)]
+    InvalidNumber(String)
+}
+
+fn parse_minutes(s: &str) -> Result<Duration, ParseTimeControlError> {
+    match s.parse::<f64>() {
+        Ok(minutes) if minutes.is_finite() && minutes >= 0.0 => Ok(Duration::from_millis((minutes * 60_000.0) as u64)),
+        _ => Err(ParseTimeControlError::InvalidNumber(s.to_string()))
+    }
+}
+fn parse_seconds(s: &str) -> Result<Duration, ParseTimeControlError> {
+    match s.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(Duration::from_millis((seconds * 1000.0) as u64)),
+        _ => Err(ParseTimeControlError::InvalidNumber(s.to_string()))
+    }
+}
+/// Parses the common shorthand: `5` is five minutes sudden death, `5+3` adds a
+/// three second increment, `5d3` has a three second delay and `40/90` is 90
+/// minutes for every 40 moves
+impl FromStr for TimeControl {
+    type Err = ParseTimeControlError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(i) = s.find('+') {
+            Ok(TimeControl::Increment {
+                time: parse_minutes(&s[..i])?,
+                increment: parse_seconds(&s[i+1..])?
+            })
+        } else if let Some(i) = s.find('d') {
+            Ok(TimeControl::Delay {
+                time: parse_minutes(&s[..i])?,
+                delay: parse_seconds(&s[i+1..])?
+            })
+        } else if let Some(i) = s.find('/') {
+            let moves = &s[..i];
+            match moves.parse() {
+                Ok(0) | Err(_) => Err(ParseTimeControlError::InvalidNumber(moves.to_string())),
+                Ok(moves) => Ok(TimeControl::MovesPerPeriod {
+                    moves,
+                    time: parse_minutes(&s[i+1..])?
+                })
+            }
+        } else if s.is_empty() {
+            Err(ParseTimeControlError::InvalidFormat)
+        } else {
+            Ok(TimeControl::SuddenDeath { time: parse_minutes(s)? })
+        }
+    }
+}
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let minutes = |time: Duration| time.as_millis() as f64 / 60_000.0;
+        let seconds = |time: Duration| time.as_millis() as f64 / 1000.0;
+        match *self {
+            TimeControl::SuddenDeath { time } => write!(f, "{}", minutes(time)),
+            TimeControl::Increment { time, increment } => write!(f, "{}+{}", minutes(time), seconds(increment)),
+            TimeControl::Delay { time, delay } => write!(f, "{}d{}", minutes(time), seconds(delay)),
+            TimeControl::MovesPerPeriod { moves, time } => write!(f, "{}/{}", moves, minutes(time))
+        }
+    }
+}
+
+/// How long to think about a move
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeBudget {
+    /// Don't start searching deeper after this long
+    pub soft: Duration,
+    /// Stop searching after this long, no matter what
+    pub hard: Duration
+}
+impl TimeBudget {
+    /// Search limits that stay within this budget
+    pub fn limits(self) -> SearchLimits {
+        SearchLimits {
+            soft_time: Some(self.soft),
+            move_time: Some(self.hard),
+            ..SearchLimits::default()
+        }
+    }
+}
+
+/// Decide how long to think about a move, given the remaining time, how many
+/// moves it has to last for if known, and the time gained back after the move
+pub fn allocate(remaining: Duration, moves_to_go: Option<u32>, increment: Duration) -> TimeBudget {
+    let overhead = (remaining / 20).min(MAX_OVERHEAD);
+    let available = remaining - overhead;
+    let moves = moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+
+    let soft = (available / moves + increment).min(available);
+    // Don't risk more than half the time on a single move, unless it's the
+    // last one before more time is added
+    let hard = (soft * 4).min(soft.max(available / 2));
+    TimeBudget { soft, hard }
+}
+
+/// The state of the clock before a side was charged for a move, so that the
+/// move can be taken back
+#[derive(Clone, Copy, Debug)]
+struct Charge {
+    side: Side,
+    remaining: Duration,
+    flagged: Option<Side>
+}
+
+/// A chess clock counting down the time of both sides. Only one side's time
+/// runs at once.
+#[derive(Clone, Debug)]
+pub struct Clock {
+    control: TimeControl,
+    remaining_black: Duration,
+    remaining_white: Duration,
+    moves_black: u32,
+    moves_white: u32,
+    running: Option<(Side, Instant)>,
+    flagged: Option<Side>,
+    charges: Vec<Charge>
+}
+impl Clock {
+    /// Create a new, stopped clock
+    pub fn new(control: TimeControl) -> Self {
+        Self {
+            control,
+            remaining_black: control.initial(),
+            remaining_white: control.initial(),
+            moves_black: 0,
+            moves_white: 0,
+            running: None,
+            flagged: None,
+            charges: Vec::new()
+        }
+    }
+    /// Return the time control of this clock
+    pub fn control(&self) -> TimeControl {
+        self.control
+    }
+    /// Return the side whose time is running, if any
+    pub fn running(&self) -> Option<Side> {
+        self.running.map(|(side, _)| side)
+    }
+    fn remaining_mut(&mut self, side: Side) -> &mut Duration {
+        match side {
+            Side::Black => &mut self.remaining_black,
+            Side::White => &mut self.remaining_white
+        }
+    }
+    /// Return the time the specified side has left, including the time spent
+    /// on the move in progress
+    pub fn remaining(&self, side: Side) -> Duration {
+        let remaining = match side {
+            Side::Black => self.remaining_black,
+            Side::White => self.remaining_white
+        };
+        match self.running {
+            Some((running, start)) if running == side => remaining.checked_sub(start.elapsed()).unwrap_or_default(),
+            _ => remaining
+        }
+    }
+    /// Return the number of moves the specified side has made
+    pub fn moves(&self, side: Side) -> u32 {
+        match side {
+            Side::Black => self.moves_black,
+            Side::White => self.moves_white
+        }
+    }
+    /// Return how many more moves the specified side has to make before
+    /// getting more time, if the time control works like that
+    pub fn moves_to_go(&self, side: Side) -> Option<u32> {
+        match self.control {
+            TimeControl::MovesPerPeriod { moves, .. } => Some(moves - self.moves(side) % moves),
+            _ => None
+        }
+    }
+    /// Return the side that ran out of time first, if any
+    pub fn flagged(&self) -> Option<Side> {
+        self.flagged.or_else(|| {
+            self.running()
+                .filter(|&side| self.remaining(side) == Duration::from_secs(0))
+        })
+    }
+
+    /// Charge the specified side for a move that took `elapsed`, adding any
+    /// time it gains back from the time control. Useful when the time is
+    /// measured elsewhere, otherwise see `start`.
+    pub fn charge(&mut self, side: Side, elapsed: Duration) {
+        let charge = Charge {
+            side,
+            remaining: *self.remaining_mut(side),
+            flagged: self.flagged
+        };
+        self.charges.push(charge);
+        let control = self.control;
+        let moves = match side {
+            Side::Black => &mut self.moves_black,
+            Side::White => &mut self.moves_white
+        };
+        *moves += 1;
+        let moves = *moves;
+
+        let remaining = self.remaining_mut(side);
+        match remaining.checked_sub(elapsed) {
+            Some(left) => *remaining = left,
+            None => {
+                *remaining = Duration::from_secs(0);
+                self.flagged = self.flagged.or(Some(side));
+                return;
+            }
+        }
+        match control {
+            TimeControl::SuddenDeath { .. } => (),
+            TimeControl::Increment { increment, .. } => *remaining += increment,
+            TimeControl::Delay { delay, .. } => *remaining += elapsed.min(delay),
+            TimeControl::MovesPerPeriod { moves: period, time } => if moves % period == 0 {
+                *remaining += time;
+            }
+        }
+    }
+    /// Start the specified side's time. If the other side's time was running,
+    /// its move is over and it's charged for it.
+    pub fn start(&mut self, side: Side) {
+        if self.running() != Some(side) {
+            self.stop();
+            self.running = Some((side, Instant::now()));
+        }
+    }
+    /// Take back the last move of the specified side, who's to move again:
+    /// refund the time it was charged along with anything the time control
+    /// added, and start its time anew. The move in progress is forgotten
+    /// without charging anyone. If the last move wasn't made by this side,
+    /// for example because the clock was set up in the middle of a game,
+    /// only its time is started.
+    pub fn take_back(&mut self, side: Side) {
+        if self.charges.last().map(|charge| charge.side) == Some(side) {
+            let charge = self.charges.pop().unwrap();
+            *self.remaining_mut(side) = charge.remaining;
+            match side {
+                Side::Black => self.moves_black -= 1,
+                Side::White => self.moves_white -= 1
+            }
+            self.flagged = charge.flagged;
+        }
+        self.running = Some((side, Instant::now()));
+    }
+    /// Stop the clock. If a side's time was running, its move is over and it's
+    /// charged for it.
+    pub fn stop(&mut self) {
+        if let Some((side, start)) = self.running.take() {
+            self.charge(side, start.elapsed());
+        }
+    }
+
+    /// Decide how long the specified side should think about its next move
+    pub fn budget(&self, side: Side) -> TimeBudget {
+        let increment = match self.control {
+            TimeControl::Increment { increment, .. } => increment,
+            TimeControl::Delay { delay, .. } => delay,
+            _ => Duration::from_secs(0)
+        };
+        allocate(self.remaining(side), self.moves_to_go(side), increment)
+    }
+}
+/// Shows the remaining time of both sides, like `white 4:59.8, black 5:00.0`
+impl fmt::Display for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for &(side, name) in &[(Side::White, "white"), (Side::Black, "black")] {
+            if !first {
+                write!(f, ", ")?;
+            }
+            first = false;
+
+            let millis = self.remaining(side).as_millis();
+            write!(f, "{} {}:{:02}.{}", name, millis / 60_000, millis / 1000 % 60, millis / 100 % 10)?;
+            if self.running() == Some(side) {
+                write!(f, " (running)")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("40/300".parse::<TimeControl>().unwrap(), TimeControl::MovesPerPeriod { moves: 40, time: secs(300 * 60) });
+        assert_eq!("300+5".parse::<TimeControl>().unwrap(), TimeControl::Increment { time: secs(300 * 60), increment: secs(5) });
+        assert_eq!("300d3".parse::<TimeControl>().unwrap(), TimeControl::Delay { time: secs(300 * 60), delay: secs(3) });
+        assert_eq!("5".parse::<TimeControl>().unwrap(), TimeControl::SuddenDeath { time: secs(5 * 60) });
+        assert_eq!("0.5+0.1".parse::<TimeControl>().unwrap(), TimeControl::Increment { time: secs(30), increment: millis(100) });
+    }
+    #[test]
+    fn parse_malformed() {
+        for &s in &["", "+", "5+", "+3", "d3", "5d", "abc", "5+x", "-5", "5+-3", "0/5", "x/5", "40/", "inf", "NaN+1", "5+3+2"] {
+            assert!(s.parse::<TimeControl>().is_err(), "{:?} parsed", s);
+        }
+    }
+    #[test]
+    fn display() {
+        for &s in &["40/300", "300+5", "300d3", "5", "0.5+0.1"] {
+            let control: TimeControl = s.parse().unwrap();
+            assert_eq!(control.to_string(), s);
+            assert_eq!(control.to_string().parse::<TimeControl>().unwrap(), control);
+        }
+    }
+    #[test]
+    fn allocate_normally() {
+        let budget = allocate(secs(300), None, secs(0));
+        assert!(budget.soft > secs(5) && budget.soft < secs(15));
+        assert!(budget.hard >= budget.soft && budget.hard <= secs(150));
+
+        // The last move before the time control may use everything
+        let budget = allocate(secs(60), Some(1), secs(0));
+        assert_eq!(budget.soft, budget.hard);
+        assert!(budget.hard > secs(59) && budget.hard < secs(60));
+
+        // An increment can be spent right away
+        assert!(allocate(secs(60), None, secs(5)).soft > allocate(secs(60), None, secs(0)).soft + secs(4));
+    }
+    #[test]
+    fn allocate_little_time() {
+        for &remaining in &[0, 1, 10, 49, 50, 51, 100, 999, 1000, 2000] {
+            let remaining = millis(remaining);
+            for &moves_to_go in &[None, Some(0), Some(1), Some(2), Some(40)] {
+                for &increment in &[millis(0), millis(100), secs(10)] {
+                    let budget = allocate(remaining, moves_to_go, increment);
+                    assert!(budget.soft <= budget.hard, "{:?}", budget);
+                    assert!(budget.hard <= remaining, "{:?} with {:?} left", budget, remaining);
+                    if remaining > millis(0) {
+                        assert!(budget.hard < remaining, "{:?} leaves no time to move", budget);
+                    }
+                }
+            }
+        }
+    }
+    #[test]
+    fn charge_increment() {
+        let mut clock = Clock::new("1+2".parse().unwrap());
+        clock.charge(Side::White, secs(5));
+        assert_eq!(clock.remaining(Side::White), secs(57));
+        assert_eq!(clock.remaining(Side::Black), secs(60));
+        assert_eq!(clock.moves(Side::White), 1);
+        assert_eq!(clock.moves_to_go(Side::White), None);
+    }
+    #[test]
+    fn charge_delay() {
+        let mut clock = Clock::new("1d3".parse().unwrap());
+        // A fast move costs nothing, a slow one only what's beyond the delay
+        clock.charge(Side::Black, secs(2));
+        assert_eq!(clock.remaining(Side::Black), secs(60));
+        clock.charge(Side::Black, secs(10));
+        assert_eq!(clock.remaining(Side::Black), secs(53));
+    }
+    #[test]
+    fn period_reset() {
+        let mut clock = Clock::new("3/1".parse().unwrap());
+        assert_eq!(clock.moves_to_go(Side::White), Some(3));
+        clock.charge(Side::White, secs(10));
+        clock.charge(Side::White, secs(10));
+        assert_eq!(clock.moves_to_go(Side::White), Some(1));
+        assert_eq!(clock.remaining(Side::White), secs(40));
+
+        // The third move completes the period, and the time is added on top
+        // of what's left
+        clock.charge(Side::White, secs(10));
+        assert_eq!(clock.remaining(Side::White), secs(90));
+        assert_eq!(clock.moves_to_go(Side::White), Some(3));
+        clock.charge(Side::White, secs(10));
+        assert_eq!(clock.remaining(Side::White), secs(80));
+        assert_eq!(clock.moves_to_go(Side::White), Some(2));
+
+        // The other side has its own period
+        assert_eq!(clock.moves_to_go(Side::Black), Some(3));
+        assert_eq!(clock.remaining(Side::Black), secs(60));
+    }
+    #[test]
+    fn take_back() {
+        // White's second move completes a period and gains time, and then
+        // it's taken back while Black is thinking
+        let mut clock = Clock::new("2/1".parse().unwrap());
+        clock.charge(Side::White, secs(10));
+        clock.charge(Side::Black, secs(5));
+        clock.charge(Side::White, secs(10));
+        assert_eq!(clock.remaining(Side::White), secs(100));
+        assert_eq!(clock.moves_to_go(Side::White), Some(2));
+        clock.start(Side::Black);
+
+        // The side whose time runs loses a little more while the test runs
+        let about = |clock: &Clock, side, expected| {
+            let remaining = clock.remaining(side);
+            assert!(remaining <= expected && remaining > expected - secs(1), "{:?} left", remaining);
+        };
+
+        clock.take_back(Side::White);
+        assert_eq!(clock.running(), Some(Side::White));
+        about(&clock, Side::White, secs(50));
+        assert_eq!(clock.moves(Side::White), 1);
+        assert_eq!(clock.moves_to_go(Side::White), Some(1));
+        assert_eq!(clock.remaining(Side::Black), secs(55));
+        assert_eq!(clock.moves(Side::Black), 1);
+
+        // Playing the move again counts it once
+        clock.charge(Side::White, secs(10));
+        assert_eq!(clock.moves(Side::White), 2);
+        about(&clock, Side::White, secs(100));
+
+        // Taking back a move that lost on time undoes the flag too
+        clock.charge(Side::Black, secs(60));
+        assert_eq!(clock.flagged(), Some(Side::Black));
+        clock.take_back(Side::Black);
+        assert_eq!(clock.flagged(), None);
+        about(&clock, Side::Black, secs(55));
+
+        // Nothing to refund if the side didn't make the last move
+        let mut clock = Clock::new("1".parse().unwrap());
+        clock.start(Side::White);
+        clock.take_back(Side::Black);
+        assert_eq!(clock.running(), Some(Side::Black));
+        assert_eq!(clock.remaining(Side::White), secs(60));
+        assert_eq!(clock.moves(Side::White), 0);
+    }
+    #[test]
+    fn flag() {
+        let mut clock = Clock::new("1+10".parse().unwrap());
+        assert_eq!(clock.flagged(), None);
+        clock.charge(Side::White, secs(61));
+        assert_eq!(clock.flagged(), Some(Side::White));
+        assert_eq!(clock.remaining(Side::White), secs(0));
+        // Running out of time isn't undone by an increment
+        clock.charge(Side::Black, secs(61));
+        assert_eq!(clock.flagged(), Some(Side::White));
+    }
+}
@@ -0,0 +1,449 @@
+use crate::{
+    board::Board,
+    game::Game,
+    king_safety,
+    pawns::PawnTable,
+    piece::PieceKind,
+    pst,
+    search::{Search, SearchLimits},
+    transposition::TranspositionTable,
+    Pos,
+    Side
+};
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Neg, Sub, SubAssign}
+};
+
+/// A way of judging how good a position is, used by the search at its leaves
+pub trait Evaluator {
+    /// Return the score of the board relative to the side to move: positive
+    /// if it's ahead, negative if it's behind. A pawn should be worth about
+    /// 100. Checkmates and draws are handled by the search, so this is only
+    /// called for positions where the game goes on.
+    fn evaluate(&mut self, board: &Board) -> i32;
+    /// Return the evaluation of a board split up into its terms, for each
+    /// side. Its `score` is `evaluate` seen from white's point of view.
+    fn breakdown(&mut self, board: &Board) -> Breakdown;
+}
+impl<E: Evaluator + ?Sized> Evaluator for &mut E {
+    fn evaluate(&mut self, board: &Board) -> i32 {
+        (**self).evaluate(board)
+    }
+    fn breakdown(&mut self, board: &Board) -> Breakdown {
+        (**self).breakdown(board)
+    }
+}
+
+/// The original evaluation, see `Board::score`: material worth, plus one
+/// point for every occupied square a piece can move to
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Classic;
+impl Evaluator for Classic {
+    fn evaluate(&mut self, board: &Board) -> i32 {
+        let side = board.side_to_move();
+        board.score(side) - board.score(!side)
+    }
+    /// Only has material and mobility, the same in every phase
+    fn breakdown(&mut self, board: &Board) -> Breakdown {
+        let mut breakdown = Breakdown {
+            phase: game_phase(board),
+            ..Breakdown::default()
+        };
+        for &side in &[Side::White, Side::Black] {
+            let mut material = 0;
+            let mut pieces = board.pieces(side);
+            while let Some((_, piece)) = pieces.next(board) {
+                material += piece.kind.worth() as i32 * 100;
+            }
+            // Everything else `Board::score` counts is mobility
+            let mobility = board.score(side) - material;
+            *breakdown.get_mut(side, Term::Material) = Score::new(material, material);
+            *breakdown.get_mut(side, Term::Mobility) = Score::new(mobility, mobility);
+        }
+        breakdown
+    }
+}
+
+/// A score with separate values for the middlegame and the endgame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32
+}
+impl Score {
+    pub fn new(mg: i32, eg: i32) -> Self {
+        Self { mg, eg }
+    }
+    /// Interpolate between the middlegame and the endgame value, depending on
+    /// the game phase as returned by `game_phase`
+    pub fn taper(self, phase: i32) -> i32 {
+        let phase = phase.max(0).min(pst::MAX_PHASE);
+        (self.mg * phase + self.eg * (pst::MAX_PHASE - phase)) / pst::MAX_PHASE
+    }
+}
+impl Add for Score {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self::Output {
+        Score::new(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+impl Sub for Score {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self::Output {
+        Score::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+impl AddAssign for Score {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+impl SubAssign for Score {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+impl Neg for Score {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Score::new(-self.mg, -self.eg)
+    }
+}
+
+/// Return how far from the endgame the game is, judging from the pieces that
+/// are left: `pst::MAX_PHASE` with all pieces on the board, down to 0 when
+/// only kings and pawns are left
+pub fn game_phase(board: &Board) -> i32 {
+    let phase: i32 = board.iter()
+        .flat_map(|row| row.iter())
+        .filter_map(|piece| piece.map(|piece| pst::phase_weight(piece.kind)))
+        .sum();
+    phase.min(pst::MAX_PHASE)
+}
+
+/// One of the terms an evaluation adds up, see `Breakdown`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Term {
+    /// The worth of the pieces, see `pst::material`
+    Material,
+    /// How many squares the pieces can move to, see `mobility`
+    Mobility,
+    /// Where the pieces stand, see `pst::piece_square`
+    PieceSquare,
+    /// Pawn structure, see `pawns`
+    Pawns,
+    /// How well the king is sheltered, see `king_safety`
+    KingSafety
+}
+impl Term {
+    /// All terms, in the order they're printed
+    pub const ALL: [Term; 5] = [Term::Material, Term::Mobility, Term::PieceSquare, Term::Pawns, Term::KingSafety];
+
+    /// Return the name of this term as shown to the user
+    pub fn name(self) -> &'static str {
+        match self {
+            Term::Material => "Material",
+            Term::Mobility => "Mobility",
+            Term::PieceSquare => "Piece-square",
+            Term::Pawns => "Pawns",
+            Term::KingSafety => "King safety"
+        }
+    }
+}
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The evaluation of a board split up into its terms, for each side and
+/// phase. Useful to see why the engine likes or dislikes a position. The
+/// `Display` implementation prints it as a table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Breakdown {
+    white: [Score; Term::ALL.len()],
+    black: [Score; Term::ALL.len()],
+    /// The game phase the terms are interpolated with, see `game_phase`
+    pub phase: i32
+}
+impl Breakdown {
+    /// Return the score of one side for a term
+    pub fn get(&self, side: Side, term: Term) -> Score {
+        match side {
+            Side::White => self.white[term as usize],
+            Side::Black => self.black[term as usize]
+        }
+    }
+    fn get_mut(&mut self, side: Side, term: Term) -> &mut Score {
+        match side {
+            Side::White => &mut self.white[term as usize],
+            Side::Black => &mut self.black[term as usize]
+        }
+    }
+    /// Return the score of one side summed over all terms
+    pub fn side(&self, side: Side) -> Score {
+        Term::ALL.iter().fold(Score::default(), |sum, &term| sum + self.get(side, term))
+    }
+    /// Return white's score minus black's score for a term
+    pub fn term(&self, term: Term) -> Score {
+        self.get(Side::White, term) - self.get(Side::Black, term)
+    }
+    /// Return the final evaluation from white's point of view, interpolated
+    /// between the middlegame and endgame
+    pub fn score(&self) -> i32 {
+        (self.side(Side::White) - self.side(Side::Black)).taper(self.phase)
+    }
+}
+impl fmt::Display for Breakdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn row(f: &mut fmt::Formatter, name: &str, scores: [Score; 3]) -> fmt::Result {
+            write!(f, "{:>12}", name)?;
+            for score in &scores {
+                write!(f, " | {:>5} {:>5}", score.mg, score.eg)?;
+            }
+            writeln!(f)
+        }
+
+        writeln!(f, "{:>12} | {:^11} | {:^11} | {:>7}", "Term", "White", "Black", "Total")?;
+        writeln!(f, "{:>12} | {:>5} {:>5} | {:>5} {:>5} | {:>5} {:>5}", "", "MG", "EG", "MG", "EG", "MG", "EG")?;
+        writeln!(f, "{}", "-".repeat(13) + &"+-------------".repeat(3))?;
+        for &term in &Term::ALL {
+            row(f, term.name(), [self.get(Side::White, term), self.get(Side::Black, term), self.term(term)])?;
+        }
+        writeln!(f, "{}", "-".repeat(13) + &"+-------------".repeat(3))?;
+        let (white, black) = (self.side(Side::White), self.side(Side::Black));
+        row(f, "Total", [white, black, white - black])?;
+        writeln!(f)?;
+        writeln!(f, "Game phase: {}/{}", self.phase, pst::MAX_PHASE)?;
+        write!(f, "Final evaluation: {} (white's point of view)", self.score())
+    }
+}
+
+/// Return the bonus for every square a piece of the specified kind can move
+/// to. Pawns and kings get nothing.
+fn mobility_weight(kind: PieceKind) -> Score {
+    match kind {
+        PieceKind::Knight => Score::new(4, 4),
+        PieceKind::Bishop => Score::new(5, 5),
+        PieceKind::Rook => Score::new(2, 4),
+        PieceKind::Queen => Score::new(1, 2),
+        PieceKind::Pawn | PieceKind::King => Score::default()
+    }
+}
+/// Return the mobility bonus for the piece on `pos`: the number of squares
+/// it can move to, weighed by its kind
+pub fn mobility(board: &Board, pos: Pos) -> Score {
+    let weight = match board.get(pos) {
+        Some(piece) => mobility_weight(piece.kind),
+        None => return Score::default()
+    };
+    if weight == Score::default() {
+        return weight;
+    }
+    let mut squares = 0;
+    let mut moves = board.moves_for(pos);
+    while moves.next(board).is_some() {
+        squares += 1;
+    }
+    Score::new(weight.mg * squares, weight.eg * squares)
+}
+
+/// Material, mobility, piece-square tables, pawn structure and king safety,
+/// interpolated between their middlegame and endgame values depending on the
+/// game phase. This makes the engine develop and centralise its pieces, care
+/// about which pawns it pushes, and keep its king sheltered until the queens
+/// are gone, after which it walks the king forward.
+#[derive(Clone, Debug, Default)]
+pub struct Tapered {
+    /// The cache of pawn structures, see `pawns`
+    pub pawns: PawnTable
+}
+impl Evaluator for Tapered {
+    fn evaluate(&mut self, board: &Board) -> i32 {
+        let score = self.breakdown(board).score();
+        match board.side_to_move() {
+            Side::White => score,
+            Side::Black => -score
+        }
+    }
+    fn breakdown(&mut self, board: &Board) -> Breakdown {
+        let pawns = self.pawns.get(board);
+        let mut breakdown = Breakdown {
+            phase: game_phase(board),
+            ..Breakdown::default()
+        };
+        for &side in &[Side::White, Side::Black] {
+            let mut pieces = board.pieces(side);
+            while let Some((pos, piece)) = pieces.next(board) {
+                *breakdown.get_mut(side, Term::Material) += pst::material(piece.kind);
+                *breakdown.get_mut(side, Term::Mobility) += mobility(board, pos);
+                *breakdown.get_mut(side, Term::PieceSquare) += pst::piece_square(piece, pos);
+            }
+            *breakdown.get_mut(side, Term::Pawns) = pawns.score(board, side);
+            *breakdown.get_mut(side, Term::KingSafety) = king_safety::score(board, side);
+        }
+        breakdown
+    }
+}
+
+/// The evaluators a user can choose between in the front-ends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Evaluation {
+    /// See `Classic`
+    Classic,
+    /// See `Tapered`
+    Tapered
+}
+impl Default for Evaluation {
+    fn default() -> Self {
+        Evaluation::Classic
+    }
+}
+impl Evaluation {
+    /// All evaluations, the default first
+    pub const ALL: [Evaluation; 2] = [Evaluation::Classic, Evaluation::Tapered];
+
+    /// Return the name of this evaluation as shown to the user
+    pub fn name(self) -> &'static str {
+        match self {
+            Evaluation::Classic => "Classic",
+            Evaluation::Tapered => "Tapered"
+        }
+    }
+    /// Look up an evaluation by its name, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|evaluation| evaluation.name().eq_ignore_ascii_case(name))
+    }
+    /// Create a new evaluator of this kind. Keep it around between searches
+    /// so its caches are reused.
+    pub fn evaluator(self) -> Box<dyn Evaluator + Send> {
+        match self {
+            Evaluation::Classic => Box::new(Classic),
+            Evaluation::Tapered => Box::new(Tapered::default())
+        }
+    }
+}
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Play a game between two evaluators from the specified board, each
+/// searching within `limits` with their own transposition table, until the
+/// game ends or `max_moves` moves have been made. Useful for comparing
+/// evaluators head-to-head.
+pub fn play<'a>(
+    board: Board,
+    white: &'a mut dyn Evaluator,
+    black: &'a mut dyn Evaluator,
+    limits: SearchLimits,
+    max_moves: usize
+) -> Game {
+    let mut game = Game::new(board);
+    let mut tables = [TranspositionTable::default(), TranspositionTable::default()];
+
+    while game.history().len() < max_moves && game.outcome().is_none() {
+        let (evaluator, table) = match game.board().side_to_move() {
+            Side::White => (&mut *white, &mut tables[0]),
+            Side::Black => (&mut *black, &mut tables[1])
+        };
+        let mut search = Search::new(limits, table);
+        search.evaluator = Some(evaluator);
+        match game.search(&mut search, |_| ()) {
+            Some(result) => game.make_move(result.move_),
+            None => break
+        }
+    }
+    game
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return the FEN of the same position with the colours swapped, so that
+    /// it should be exactly as good for the side to move
+    fn mirror(fen: &str) -> String {
+        let swap = |s: &str| -> String {
+            s.chars()
+                .map(|c| if c.is_ascii_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() })
+                .collect()
+        };
+        let fields: Vec<_> = fen.split(' ').collect();
+        let placement: Vec<_> = fields[0].split('/').rev().map(swap).collect();
+        let side = if fields[1] == "w" { "b" } else { "w" };
+        let en_passant = match fields[3].as_bytes() {
+            &[file, rank] => format!("{}{}", file as char, (b'9' - rank + b'0') as char),
+            _ => fields[3].to_string()
+        };
+        format!("{} {} {} {} {} {}", placement.join("/"), side, swap(fields[2]), en_passant, fields[4], fields[5])
+    }
+
+    #[test]
+    fn symmetry() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "6k1/5ppp/8/8/7q/5n2/5PPP/6K1 b - - 0 1"
+        ];
+        let mut evaluator = Tapered::default();
+        for &fen in &fens {
+            let board = Board::from_fen(fen).unwrap();
+            let mirrored = Board::from_fen(&mirror(fen)).unwrap();
+            assert_eq!(evaluator.evaluate(&board), evaluator.evaluate(&mirrored), "{} and {}", board, mirrored);
+
+            let (breakdown, other) = (evaluator.breakdown(&board), evaluator.breakdown(&mirrored));
+            for &term in &Term::ALL {
+                assert_eq!(breakdown.get(Side::White, term), other.get(Side::Black, term), "{} in {}", term, board);
+                assert_eq!(breakdown.get(Side::Black, term), other.get(Side::White, term), "{} in {}", term, board);
+            }
+            assert_eq!(breakdown.score(), -other.score());
+        }
+    }
+    #[test]
+    fn side_to_move() {
+        // An extra pawn is good for white, and just as bad for black
+        let mut evaluator = Tapered::default();
+        let white = Board::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        let black = Board::from_fen("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1").unwrap();
+        assert!(evaluator.evaluate(&white) > 0);
+        assert_eq!(evaluator.evaluate(&black), -evaluator.evaluate(&white));
+        assert_eq!(evaluator.breakdown(&white).score(), evaluator.evaluate(&white));
+    }
+    #[test]
+    fn classic_breakdown() {
+        let board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1").unwrap();
+        let breakdown = Classic.breakdown(&board);
+        assert_eq!(breakdown.score(), -Classic.evaluate(&board));
+        assert_eq!(breakdown.get(Side::White, Term::Material), Score::new(3900, 3900));
+        for &side in &[Side::White, Side::Black] {
+            let total = breakdown.side(side);
+            assert_eq!((total.mg, total.eg), (board.score(side), board.score(side)));
+            assert_eq!(breakdown.get(side, Term::PieceSquare), Score::default());
+        }
+    }
+    #[test]
+    fn classic_promotion() {
+        // Capturing the knight while promoting is one reachable piece, not four
+        let with = Board::from_fen("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let without = Board::from_fen("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(with.score(Side::White) - without.score(Side::White), 1);
+    }
+    #[test]
+    fn evaluation_names() {
+        for &evaluation in &Evaluation::ALL {
+            assert_eq!(Evaluation::from_name(evaluation.name()), Some(evaluation));
+        }
+        assert_eq!(Evaluation::from_name("tapered"), Some(Evaluation::Tapered));
+        assert_eq!(Evaluation::from_name("nope"), None);
+        assert_eq!(Evaluation::ALL[0], Evaluation::default());
+    }
+}
@@ -0,0 +1,354 @@
+use crate::{
+    board::{self, Board, Castling},
+    piece::{Piece, PieceKind},
+    ParsePosError,
+    Pos,
+    Side
+};
+use std::fmt::{self, Write};
+
+/// The FEN string of the default starting position
+pub const STARTING_POSITION: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// An error parsing a board from a FEN string
+#[derive(Debug, Fail)]
+pub enum ParseFenError {
+    #[fail(display = "missing {} field in FEN string", _0)]
+    MissingField(&'static str),
+    #[fail(display = "unexpected trailing data in FEN string")]
+    TrailingData,
+    #[fail(display = "piece placement must have {} ranks of {} squares", _0, _0)]
+    InvalidDimensions(i8),
+    #[fail(display = "invalid piece character {:?}", _0)]
+    InvalidPiece(char),
+    #[fail(display = "invalid side to move {:?}, expected w or b", _0)]
+    InvalidSide(String),
+    #[fail(display = "invalid castling rights {:?}", _0)]
+    InvalidCastling(String),
+    #[fail(display = "invalid en passant square: {}", _0)]
+    InvalidEnPassant(#[cause] ParsePosError),
+    #[fail(display = "en passant square {:?} is not behind a pawn that just moved", _0)]
+    MisplacedEnPassant(String),
+    #[fail(display = "invalid move counter {:?}", _0)]
+    InvalidClock(String)
+}
+
+pub(crate) fn write_pos<W: Write>(out: &mut W, pos: Pos) -> fmt::Result {
+    let Pos(x, y) = pos;
+    write!(out, "{}{}", (b'a' + x as u8) as char, board::WIDTH - y)
+}
+
+impl Board {
+    /// Parse a board from a string in Forsyth–Edwards Notation. The halfmove
+    /// clock and fullmove number may be omitted, in which case they default to
+    /// 0 and 1.
+    pub fn from_fen(fen: &str) -> Result<Self, ParseFenError> {
+        let mut fields = fen.split_whitespace();
+
+        let placement = fields.next().ok_or(ParseFenError::MissingField("piece placement"))?;
+        let side = fields.next().ok_or(ParseFenError::MissingField("side to move"))?;
+        let castling = fields.next().ok_or(ParseFenError::MissingField("castling"))?;
+        let en_passant = fields.next().ok_or(ParseFenError::MissingField("en passant"))?;
+        let halfmove = fields.next();
+        let fullmove = fields.next();
+
+        if fields.next().is_some() {
+            return Err(ParseFenError::TrailingData);
+        }
+
+        let mut pieces = [[None; board::WIDTH as usize]; board::WIDTH as usize];
+        let mut ranks = placement.split('/');
+        for row in pieces.iter_mut() {
+            let rank = ranks.next().ok_or(ParseFenError::InvalidDimensions(board::WIDTH))?;
+            let mut x = 0;
+            for c in rank.chars() {
+                match c.to_digit(10) {
+                    Some(empty @ 1..=8) => x += empty as usize,
+                    Some(_) => return Err(ParseFenError::InvalidDimensions(board::WIDTH)),
+                    None => {
+                        let piece = Piece::from_ascii(c).ok_or(ParseFenError::InvalidPiece(c))?;
+                        if x < row.len() {
+                            row[x] = Some(piece);
+                        }
+                        x += 1;
+                    }
+                }
+                if x > row.len() {
+                    return Err(ParseFenError::InvalidDimensions(board::WIDTH));
+                }
+            }
+            if x != row.len() {
+                return Err(ParseFenError::InvalidDimensions(board::WIDTH));
+            }
+        }
+        if ranks.next().is_some() {
+            return Err(ParseFenError::InvalidDimensions(board::WIDTH));
+        }
+
+        let side_to_move = match side {
+            "w" => Side::White,
+            "b" => Side::Black,
+            _ => return Err(ParseFenError::InvalidSide(side.to_string()))
+        };
+
+        let mut castling_black = Castling { queenside: false, kingside: false };
+        let mut castling_white = Castling { queenside: false, kingside: false };
+        if castling != "-" {
+            for c in castling.chars() {
+                let right = match c {
+                    'K' => &mut castling_white.kingside,
+                    'Q' => &mut castling_white.queenside,
+                    'k' => &mut castling_black.kingside,
+                    'q' => &mut castling_black.queenside,
+                    _ => return Err(ParseFenError::InvalidCastling(castling.to_string()))
+                };
+                // Each right may only be listed once
+                if *right {
+                    return Err(ParseFenError::InvalidCastling(castling.to_string()));
+                }
+                *right = true;
+            }
+        }
+        let en_passant = if en_passant == "-" {
+            None
+        } else {
+            let target: Pos = en_passant.parse().map_err(ParseFenError::InvalidEnPassant)?;
+            // The target square is the one the pawn skipped, but the board
+            // keeps track of the pawn itself.
+            let victim = target + board::forward(!side_to_move);
+            let get = |Pos(x, y): Pos| pieces[y as usize][x as usize];
+            let pawn = Piece { kind: PieceKind::Pawn, side: !side_to_move };
+            if target.1 != board::edge_offset(!side_to_move, 2)
+                    || get(target).is_some()
+                    || get(victim) != Some(pawn) {
+                return Err(ParseFenError::MisplacedEnPassant(en_passant.to_string()));
+            }
+            Some(victim)
+        };
+
+        let parse_clock = |field: Option<&str>, default| match field {
+            Some(field) => field.parse().map_err(|_| ParseFenError::InvalidClock(field.to_string())),
+            None => Ok(default)
+        };
+        let halfmove_clock = parse_clock(halfmove, 0)?;
+        let fullmove_number = parse_clock(fullmove, 1)?;
+        // Full moves are counted from 1
+        if fullmove_number == 0 {
+            return Err(ParseFenError::InvalidClock(fullmove.unwrap_or_default().to_string()));
+        }
+
+        let mut board = Board {
+            pieces,
+            en_passant,
+            castling_black,
+            castling_white,
+            side_to_move,
+            halfmove_clock,
+            fullmove_number,
+            zobrist: 0,
+            pawn_zobrist: 0
+        };
+        // Like after a move, rights nobody can use are quietly dropped
+        board.drop_unusable_rights();
+        board.zobrist = board.compute_zobrist();
+        board.pawn_zobrist = board.compute_pawn_key();
+        Ok(board)
+    }
+    /// Return the board in Forsyth–Edwards Notation
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        self.write_fen(&mut fen).expect("writing to a string can't fail");
+        fen
+    }
+    fn write_fen<W: Write>(&self, out: &mut W) -> fmt::Result {
+        for (y, row) in self.iter().enumerate() {
+            if y > 0 {
+                out.write_char('/')?;
+            }
+            let mut empty = 0;
+            for piece in row {
+                match piece {
+                    None => empty += 1,
+                    Some(piece) => {
+                        if empty > 0 {
+                            write!(out, "{}", empty)?;
+                            empty = 0;
+                        }
+                        out.write_char(piece.to_ascii())?;
+                    }
+                }
+            }
+            if empty > 0 {
+                write!(out, "{}", empty)?;
+            }
+        }
+
+        out.write_str(match self.side_to_move {
+            Side::White => " w ",
+            Side::Black => " b "
+        })?;
+
+        let castling = [
+            (self.castling_white.kingside, 'K'),
+            (self.castling_white.queenside, 'Q'),
+            (self.castling_black.kingside, 'k'),
+            (self.castling_black.queenside, 'q')
+        ];
+        if castling.iter().all(|&(allowed, _)| !allowed) {
+            out.write_char('-')?;
+        }
+        for &(allowed, c) in &castling {
+            if allowed {
+                out.write_char(c)?;
+            }
+        }
+
+        out.write_char(' ')?;
+        match self.en_passant {
+            Some(victim) => {
+                let side = self.get(victim).map(|p| p.side).unwrap_or(!self.side_to_move);
+                write_pos(out, victim - board::forward(side))?;
+            },
+            None => out.write_char('-')?
+        }
+
+        write!(out, " {} {}", self.halfmove_clock, self.fullmove_number)
+    }
+}
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_fen(f)
+    }
+}
+impl std::str::FromStr for Board {
+    type Err = ParseFenError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_fen(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let fens = [
+            STARTING_POSITION,
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b Kq e3 0 3",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 b - - 49 123"
+        ];
+        for &fen in &fens {
+            let board = Board::from_fen(fen).unwrap();
+            assert_eq!(board.to_fen(), fen);
+            assert_eq!(board.to_string(), fen);
+            assert_eq!(fen.parse::<Board>().unwrap().position_key(), board.position_key());
+        }
+        assert_eq!(Board::default().to_fen(), STARTING_POSITION);
+        assert_eq!(Board::from_fen(STARTING_POSITION).unwrap().position_key(), Board::default().position_key());
+    }
+    #[test]
+    fn unusable_en_passant() {
+        // No white pawn next to e5 can capture it
+        let board = Board::from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2").unwrap();
+        assert_eq!(board.en_passant(), None);
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2");
+    }
+    #[test]
+    fn default_clocks() {
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - -").unwrap();
+        assert_eq!(board.halfmove_clock(), 0);
+        assert_eq!(board.fullmove_number(), 1);
+        assert_eq!(board.to_fen(), "4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 7").unwrap();
+        assert_eq!(board.halfmove_clock(), 7);
+        assert_eq!(board.fullmove_number(), 1);
+    }
+    #[test]
+    fn malformed() {
+        fn error(fen: &str) -> ParseFenError {
+            match Board::from_fen(fen) {
+                Ok(board) => panic!("{:?} parsed as {}", fen, board),
+                Err(err) => err
+            }
+        }
+        macro_rules! assert_error {
+            ($fen:expr, $pattern:pat) => {
+                match error($fen) {
+                    $pattern => (),
+                    err => panic!("{:?} gave {:?}", $fen, err)
+                }
+            };
+        }
+
+        assert_error!("", ParseFenError::MissingField("piece placement"));
+        assert_error!("8/8/8/8/8/8/8/8", ParseFenError::MissingField("side to move"));
+        assert_error!("8/8/8/8/8/8/8/8 w", ParseFenError::MissingField("castling"));
+        assert_error!("8/8/8/8/8/8/8/8 w -", ParseFenError::MissingField("en passant"));
+        assert_error!("8/8/8/8/8/8/8/8 w - - 0 1 extra", ParseFenError::TrailingData);
+
+        assert_error!("8/8/8/8/8/8/8 w - - 0 1", ParseFenError::InvalidDimensions(8));
+        assert_error!("8/8/8/8/8/8/8/8/8 w - - 0 1", ParseFenError::InvalidDimensions(8));
+        assert_error!("8/8/8/8/8/8/8/7 w - - 0 1", ParseFenError::InvalidDimensions(8));
+        assert_error!("8/8/8/8/8/8/8/ppppppppp w - - 0 1", ParseFenError::InvalidDimensions(8));
+        assert_error!("08/8/8/8/8/8/8/8 w - - 0 1", ParseFenError::InvalidDimensions(8));
+        assert_error!("9/8/8/8/8/8/8/8 w - - 0 1", ParseFenError::InvalidDimensions(8));
+        assert_error!("7x/8/8/8/8/8/8/8 w - - 0 1", ParseFenError::InvalidPiece('x'));
+
+        assert_error!("8/8/8/8/8/8/8/8 white - - 0 1", ParseFenError::InvalidSide(_));
+        assert_error!("8/8/8/8/8/8/8/8 w KX - 0 1", ParseFenError::InvalidCastling(_));
+        assert_error!("r3k2r/8/8/8/8/8/8/R3K2R w KKqq - 0 1", ParseFenError::InvalidCastling(_));
+        assert_error!("8/8/8/8/8/8/8/8 w - z9 0 1", ParseFenError::InvalidEnPassant(_));
+        assert_error!("8/8/8/8/8/8/8/8 w - - x 1", ParseFenError::InvalidClock(_));
+        assert_error!("8/8/8/8/8/8/8/8 w - - 0 -1", ParseFenError::InvalidClock(_));
+        assert_error!("8/8/8/8/8/8/8/8 w - - 0 0", ParseFenError::InvalidClock(_));
+    }
+    #[test]
+    fn misplaced_en_passant() {
+        let misplaced = |fen| match Board::from_fen(fen) {
+            Err(ParseFenError::MisplacedEnPassant(_)) => (),
+            result => panic!("{:?} gave {:?}", fen, result)
+        };
+        // Wrong rank for the side to move
+        misplaced("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e3 0 2");
+        // No pawn in front of the target square
+        misplaced("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e6 0 1");
+        // A pawn of the wrong side
+        misplaced("rnbqkbnr/pppp1ppp/8/4P3/8/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+        // A piece other than a pawn
+        misplaced("rnbqkbnr/pppp1ppp/8/4n3/8/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+        // The target square isn't empty
+        misplaced("rnbqkbnr/pppp1ppp/4n3/4p3/8/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+    }
+    #[test]
+    fn unusable_castling() {
+        let rights = |fen| {
+            let board = Board::from_fen(fen).unwrap();
+            (board.castling_rights(Side::White), board.castling_rights(Side::Black))
+        };
+        let none = Castling { queenside: false, kingside: false };
+
+        // The king isn't on the e-file
+        assert_eq!(rights("4k3/8/8/8/8/8/8/6KR w K - 0 1"), (none, none));
+        assert_eq!(Board::from_fen("4k3/8/8/8/8/8/8/6KR w K - 0 1").unwrap().to_fen(), "4k3/8/8/8/8/8/8/6KR w - - 0 1");
+        // One rook is missing, or belongs to the other side
+        assert_eq!(
+            rights("r3k2R/8/8/8/8/8/8/4K2R w KQkq - 0 1"),
+            (Castling { queenside: false, kingside: true }, Castling { queenside: true, kingside: false })
+        );
+        // Everything in place
+        assert_eq!(rights("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1"), (Castling::default(), Castling::default()));
+    }
+    #[test]
+    fn large_clocks() {
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/8/4K3 b - - 65535 65535").unwrap();
+        let m = board.infer_move(Pos(4, 0), Pos(4, 1), None);
+        board.move_(m);
+        assert_eq!(board.halfmove_clock(), 65535);
+        assert_eq!(board.fullmove_number(), 65535);
+    }
+}
@@ -0,0 +1,380 @@
+use crate::{
+    board::{Board, Change, Move},
+    minimax::MinimaxResult,
+    piece::PieceKind,
+    search::{Iteration, Search},
+    transposition::TranspositionTable,
+    Side
+};
+use std::{
+    fmt,
+    sync::atomic::AtomicBool
+};
+
+/// The way a game has ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameOutcome {
+    /// The losing side has no legal moves while in check
+    Checkmate { winner: Side },
+    /// The side to move has no legal moves, but isn't in check
+    Stalemate,
+    /// No capture or pawn move has been made in the last 50 moves
+    FiftyMoves,
+    /// No capture or pawn move has been made in the last 75 moves
+    SeventyFiveMoves,
+    /// The same position has occurred three times
+    ThreefoldRepetition,
+    /// The same position has occurred five times
+    FivefoldRepetition,
+    /// Neither side has enough pieces left to checkmate
+    InsufficientMaterial,
+    /// The losing side gave up. Never detected by the library itself.
+    Resignation { winner: Side },
+    /// The losing side ran out of time. Never detected by the library itself.
+    Timeout { winner: Side }
+}
+impl GameOutcome {
+    /// Return the side that won the game, or `None` if it's a draw
+    pub fn winner(self) -> Option<Side> {
+        match self {
+            GameOutcome::Checkmate { winner }
+            | GameOutcome::Resignation { winner }
+            | GameOutcome::Timeout { winner } => Some(winner),
+            _ => None
+        }
+    }
+    /// Returns true if the game ended in a draw
+    pub fn is_draw(self) -> bool {
+        self.winner().is_none()
+    }
+}
+impl fmt::Display for GameOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let side_str = |side| match side {
+            Side::Black => "black",
+            Side::White => "white"
+        };
+        match *self {
+            GameOutcome::Checkmate { winner } => write!(f, "{} wins by checkmate", side_str(winner)),
+            GameOutcome::Stalemate => write!(f, "draw by stalemate"),
+            GameOutcome::FiftyMoves => write!(f, "draw by the fifty-move rule"),
+            GameOutcome::SeventyFiveMoves => write!(f, "draw by the seventy-five-move rule"),
+            GameOutcome::ThreefoldRepetition => write!(f, "draw by threefold repetition"),
+            GameOutcome::FivefoldRepetition => write!(f, "draw by fivefold repetition"),
+            GameOutcome::InsufficientMaterial => write!(f, "draw by insufficient material"),
+            GameOutcome::Resignation { winner } => write!(f, "{} wins by resignation", side_str(winner)),
+            GameOutcome::Timeout { winner } => write!(f, "{} wins on time", side_str(winner))
+        }
+    }
+}
+
+impl Board {
+    /// Return how the game has ended, judging only from this board, or `None`
+    /// if the side to move can keep playing
+    pub fn outcome(&self) -> Option<GameOutcome> {
+        let side = self.side_to_move;
+        if self.legal_moves(side).is_empty() {
+            Some(if self.check(side).is_some() {
+                GameOutcome::Checkmate { winner: !side }
+            } else {
+                GameOutcome::Stalemate
+            })
+        } else if self.is_insufficient_material() {
+            Some(GameOutcome::InsufficientMaterial)
+        } else if self.halfmove_clock >= 100 {
+            Some(GameOutcome::FiftyMoves)
+        } else {
+            None
+        }
+    }
+    /// Returns true if neither side has enough pieces left to ever checkmate,
+    /// no matter how badly the other side plays. That is the case when besides
+    /// the kings there is only a single knight, or any number of bishops that
+    /// are all on the same colour.
+    pub fn is_insufficient_material(&self) -> bool {
+        let mut knights = 0;
+        let mut bishop_colours = [false; 2];
+        for (y, row) in self.iter().enumerate() {
+            for (x, piece) in row.iter().enumerate() {
+                match piece.map(|p| p.kind) {
+                    None | Some(PieceKind::King) => (),
+                    Some(PieceKind::Knight) => knights += 1,
+                    Some(PieceKind::Bishop) => bishop_colours[(x + y) % 2] = true,
+                    Some(_) => return false
+                }
+            }
+        }
+        match (knights, bishop_colours) {
+            (0, [light, dark]) => !(light && dark),
+            (1, [false, false]) => true,
+            _ => false
+        }
+    }
+}
+
+/// One step in the history of a game
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    /// The move that was made, or `None` if the turn was passed
+    pub move_: Option<Move>,
+    /// How to revert the move
+    pub change: Change,
+    /// The position key of the board before the move
+    pub key: u64
+}
+
+/// A board along with the history of how it got there, used to detect draws
+/// that depend on earlier positions
+#[derive(Clone, Debug, Default)]
+pub struct Game {
+    board: Board,
+    history: Vec<HistoryEntry>
+}
+impl Game {
+    /// Start a new game from the specified board
+    pub fn new(board: Board) -> Self {
+        Self {
+            board,
+            history: Vec::new()
+        }
+    }
+    /// Get a reference to the current board
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+    /// Return all moves made so far, oldest first
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+    /// Return the keys of all previous positions, oldest first. This does not
+    /// include the current position.
+    pub fn keys(&self) -> Vec<u64> {
+        self.history.iter().map(|entry| entry.key).collect()
+    }
+
+    /// Make a move on the board and remember it.
+    /// Note: Like `Board::move_`, this doesn't check if the move is legal.
+    pub fn make_move(&mut self, m: Move) {
+        let key = self.board.position_key();
+        let change = self.board.move_(m);
+        self.history.push(HistoryEntry {
+            move_: Some(m),
+            change,
+            key
+        });
+    }
+    /// Pass the turn to the other side without moving anything
+    pub fn pass_turn(&mut self) {
+        let key = self.board.position_key();
+        let change = self.board.pass_turn();
+        self.history.push(HistoryEntry {
+            move_: None,
+            change,
+            key
+        });
+    }
+    /// Undo the last move. Returns `None` if there was nothing to undo,
+    /// otherwise the entry that was undone.
+    pub fn undo(&mut self) -> Option<HistoryEntry> {
+        let entry = self.history.pop()?;
+        self.board.undo(entry.change.clone());
+        Some(entry)
+    }
+
+    /// Return how many times the current position has occurred in this game,
+    /// including right now
+    pub fn repetitions(&self) -> usize {
+        let key = self.board.position_key();
+        1 + self.history.iter().filter(|entry| entry.key == key).count()
+    }
+    /// Return how the game has ended, or `None` if the side to move can keep
+    /// playing
+    pub fn outcome(&self) -> Option<GameOutcome> {
+        let outcome = self.board.outcome();
+        match outcome {
+            Some(GameOutcome::Checkmate { .. })
+            | Some(GameOutcome::Stalemate)
+            | Some(GameOutcome::InsufficientMaterial) => return outcome,
+            _ => ()
+        }
+        let repetitions = self.repetitions();
+        if repetitions >= 5 {
+            Some(GameOutcome::FivefoldRepetition)
+        } else if self.board.halfmove_clock() >= 150 {
+            Some(GameOutcome::SeventyFiveMoves)
+        } else if repetitions >= 3 {
+            Some(GameOutcome::ThreefoldRepetition)
+        } else {
+            outcome
+        }
+    }
+
+    /// Return the best move for the side to move, scoring repetitions of
+    /// earlier positions in this game as draws. Keep using the same table
+    /// throughout the game to reuse earlier results.
+    pub fn minimax(
+        &mut self,
+        depth: u8,
+        table: &mut TranspositionTable,
+        exit: Option<&AtomicBool>
+    ) -> Option<MinimaxResult> {
+        let keys = self.keys();
+        self.board.minimax_with_table(depth, &keys, table, exit)
+    }
+    /// Run an iterative deepening search for the side to move, scoring
+    /// repetitions of earlier positions in this game as draws. See
+    /// `Search::run`.
+    pub fn search<F>(&mut self, search: &mut Search, callback: F) -> Option<MinimaxResult>
+        where F: FnMut(&Iteration)
+    {
+        let keys = self.keys();
+        search.run(&mut self.board, &keys, callback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(fen: &str) -> Game {
+        Game::new(Board::from_fen(fen).unwrap())
+    }
+    fn play(game: &mut Game, moves: &[&str]) {
+        for m in moves {
+            let m = game.board().parse_move(m).unwrap();
+            game.make_move(m);
+        }
+    }
+    const SHUFFLE: [&str; 4] = ["Nf3", "Nf6", "Ng1", "Ng8"];
+
+    #[test]
+    fn repetition() {
+        let mut game = Game::default();
+        assert_eq!(game.repetitions(), 1);
+        play(&mut game, &SHUFFLE);
+        assert_eq!(game.repetitions(), 2);
+        assert_eq!(game.outcome(), None);
+        play(&mut game, &SHUFFLE);
+        assert_eq!(game.repetitions(), 3);
+        assert_eq!(game.outcome(), Some(GameOutcome::ThreefoldRepetition));
+
+        // Threefold repetition has to be claimed, so the game may go on
+        play(&mut game, &SHUFFLE[..2]);
+        assert_eq!(game.outcome(), Some(GameOutcome::ThreefoldRepetition));
+        play(&mut game, &SHUFFLE[2..]);
+        assert_eq!(game.repetitions(), 4);
+        play(&mut game, &SHUFFLE);
+        assert_eq!(game.repetitions(), 5);
+        assert_eq!(game.outcome(), Some(GameOutcome::FivefoldRepetition));
+        assert!(game.outcome().unwrap().is_draw());
+    }
+    #[test]
+    fn repetition_undone() {
+        let mut game = Game::default();
+        play(&mut game, &SHUFFLE);
+        play(&mut game, &SHUFFLE);
+        assert_eq!(game.outcome(), Some(GameOutcome::ThreefoldRepetition));
+
+        game.undo().unwrap();
+        assert_eq!(game.repetitions(), 2);
+        assert_eq!(game.outcome(), None);
+
+        // Going somewhere else instead doesn't repeat anything
+        play(&mut game, &["Nh5"]);
+        assert_eq!(game.repetitions(), 1);
+        assert_eq!(game.outcome(), None);
+        game.undo().unwrap();
+        play(&mut game, &["Ng8"]);
+        assert_eq!(game.repetitions(), 3);
+    }
+    #[test]
+    fn move_rules() {
+        let mut fifty = game("4k3/8/8/8/8/8/4P3/R3K3 w - - 99 80");
+        assert_eq!(fifty.outcome(), None);
+        play(&mut fifty, &["Ra2"]);
+        assert_eq!(fifty.board().halfmove_clock(), 100);
+        assert_eq!(fifty.board().outcome(), Some(GameOutcome::FiftyMoves));
+        assert_eq!(fifty.outcome(), Some(GameOutcome::FiftyMoves));
+
+        // A pawn move resets the count
+        let mut reset = game("4k3/8/8/8/8/8/4P3/R3K3 w - - 99 80");
+        play(&mut reset, &["e3"]);
+        assert_eq!(reset.outcome(), None);
+
+        let mut seventy_five = game("4k3/8/8/8/8/8/4P3/R3K3 w - - 148 80");
+        play(&mut seventy_five, &["Ra2"]);
+        assert_eq!(seventy_five.outcome(), Some(GameOutcome::FiftyMoves));
+        play(&mut seventy_five, &["Kd7"]);
+        assert_eq!(seventy_five.board().halfmove_clock(), 150);
+        assert_eq!(seventy_five.outcome(), Some(GameOutcome::SeventyFiveMoves));
+
+        // Checkmate on the last move still counts
+        let mut mate = game("6k1/5ppp/8/8/8/8/8/R5K1 w - - 99 80");
+        play(&mut mate, &["Ra8#"]);
+        assert_eq!(mate.outcome(), Some(GameOutcome::Checkmate { winner: Side::White }));
+    }
+    #[test]
+    fn search_mate_at_fifty_moves() {
+        let mut board = Board::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 99 80").unwrap();
+        let result = board.minimax(2, None).unwrap();
+        assert_eq!(board.to_san(result.move_), "Ra8#");
+        assert_eq!(result.score, crate::minimax::MATE - 1);
+    }
+    #[test]
+    fn mate_and_stalemate() {
+        let mut game = Game::default();
+        play(&mut game, &["f3", "e5", "g4", "Qh4#"]);
+        assert_eq!(game.outcome(), Some(GameOutcome::Checkmate { winner: Side::Black }));
+        assert_eq!(game.outcome().unwrap().winner(), Some(Side::Black));
+
+        let board = Board::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(board.outcome(), Some(GameOutcome::Stalemate));
+    }
+    #[test]
+    fn insufficient_material() {
+        let insufficient = |fen| Board::from_fen(fen).unwrap().is_insufficient_material();
+        assert!(insufficient("4k3/8/8/8/8/8/8/4K3 w - - 0 1"));
+        assert!(insufficient("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1"));
+        assert!(insufficient("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(!insufficient("2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(!insufficient("4k3/8/8/8/8/8/8/1N2K1N1 w - - 0 1"));
+        assert!(!insufficient("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+
+        assert_eq!(game("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1").outcome(), Some(GameOutcome::InsufficientMaterial));
+        assert_eq!(game("2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1").outcome(), None);
+    }
+    #[test]
+    fn search_underpromotion_draw() {
+        use crate::eval::{Breakdown, Evaluator};
+
+        /// Thinks a knight is worth more than anything, so only the search
+        /// seeing the draw stops it from promoting to one
+        struct Knights;
+        impl Evaluator for Knights {
+            fn evaluate(&mut self, board: &Board) -> i32 {
+                let side = board.side_to_move();
+                board.iter()
+                    .flat_map(|row| row.iter())
+                    .filter_map(|&piece| piece.filter(|piece| piece.kind == PieceKind::Knight))
+                    .map(|piece| if piece.side == side { 1000 } else { -1000 })
+                    .sum()
+            }
+            fn breakdown(&mut self, _board: &Board) -> Breakdown {
+                Breakdown::default()
+            }
+        }
+        let search = |fen| {
+            let mut board = Board::from_fen(fen).unwrap();
+            let mut table = TranspositionTable::new(1);
+            board.minimax_with_evaluator(1, &[], &mut table, &mut Knights, None).unwrap()
+        };
+
+        // A lone knight can't mate, so e8=N is a draw like everything else
+        assert_eq!(search("8/4P3/8/8/8/8/k7/4K3 w - - 0 1").score, 0);
+
+        // With a black pawn left it isn't
+        let result = search("8/4P3/8/8/8/8/k6p/4K3 w - - 0 1");
+        assert_eq!(result.score, 1000);
+        assert_eq!(result.move_.promotion, Some(PieceKind::Knight));
+    }
+}
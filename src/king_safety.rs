@@ -0,0 +1,173 @@
+// King safety evaluation: how well a king is sheltered by its own pawns, how
+// exposed it is along open files, and how many enemy pieces bear down on the
+// squares around it. Mating attacks need material, so all of these terms only
+// have a middlegame value and fade out as pieces are traded off, see
+// `Score::taper`.
+
+use crate::{
+    board::{self, Board},
+    eval::Score,
+    piece::{Piece, PieceKind},
+    Pos,
+    Side
+};
+
+/// Bonus for a pawn one rank in front of its king, on the king's file or one
+/// next to it
+const SHIELD_CLOSE: i32 = 15;
+/// Bonus for such a pawn two ranks in front of its king instead
+const SHIELD_FAR: i32 = 8;
+/// Penalty for a file near the king without any pawns
+const OPEN_FILE: i32 = -25;
+/// Penalty for a file near the king with only enemy pawns
+const SEMI_OPEN_FILE: i32 = -12;
+/// How dangerous an enemy piece is for every square of the king zone it
+/// attacks, by kind
+fn attack_weight(kind: PieceKind) -> i32 {
+    match kind {
+        PieceKind::Knight | PieceKind::Bishop => 8,
+        PieceKind::Rook => 12,
+        PieceKind::Queen => 20,
+        PieceKind::Pawn | PieceKind::King => 0
+    }
+}
+/// How much of the attack weight counts, in percent, by the number of
+/// attacking pieces. A lone attacker rarely achieves anything.
+const ATTACKERS_SCALE: [i32; 8] = [0, 0, 50, 75, 88, 94, 97, 99];
+
+/// Return the files around the king, which the shield and open file terms
+/// look at
+fn files(king: Pos) -> impl Iterator<Item = i8> {
+    (king.0 - 1..=king.0 + 1).filter(|x| (0..board::WIDTH).contains(x))
+}
+
+/// Return the bonus for the pawns sheltering the king of `side`
+pub fn shield(board: &Board, side: Side) -> Score {
+    let king = match board.king(side) {
+        Some(king) => king,
+        None => return Score::default()
+    };
+    let pawn = Some(Piece { kind: PieceKind::Pawn, side });
+    let forward = board::forward(side);
+
+    let mut score = 0;
+    for x in files(king) {
+        let close = Pos(x, king.1) + forward;
+        let far = close + forward;
+        if close.is_valid() && board.get(close) == pawn {
+            score += SHIELD_CLOSE;
+        } else if far.is_valid() && board.get(far) == pawn {
+            score += SHIELD_FAR;
+        }
+    }
+    Score::new(score, 0)
+}
+/// Return the penalty for open and semi-open files next to the king of
+/// `side`, which enemy rooks and queens can use to reach it
+pub fn open_files(board: &Board, side: Side) -> Score {
+    let king = match board.king(side) {
+        Some(king) => king,
+        None => return Score::default()
+    };
+
+    let mut score = 0;
+    for x in files(king) {
+        let pawns = |side| (0..board::WIDTH)
+            .any(|y| board.get(Pos(x, y)) == Some(Piece { kind: PieceKind::Pawn, side }));
+        if !pawns(side) {
+            score += if pawns(!side) { SEMI_OPEN_FILE } else { OPEN_FILE };
+        }
+    }
+    Score::new(score, 0)
+}
+/// Return the penalty for enemy pieces attacking the king zone of `side`,
+/// that is the king and the squares around it. Each attacker adds its weight
+/// for every square it attacks, and the more attackers there are, the more of
+/// that weight counts.
+pub fn attacks(board: &Board, side: Side) -> Score {
+    let king = match board.king(side) {
+        Some(king) => king,
+        None => return Score::default()
+    };
+    // Which pieces attack the zone, as a bit for every attacker's position
+    let mut attackers = 0u64;
+    let mut weight = 0;
+    for y in king.1 - 1..=king.1 + 1 {
+        for x in king.0 - 1..=king.0 + 1 {
+            let pos = Pos(x, y);
+            if !pos.is_valid() {
+                continue;
+            }
+            for attacker in board.attackers(pos, !side) {
+                let kind = board.get(attacker).map(|piece| piece.kind).unwrap_or(PieceKind::Pawn);
+                if attack_weight(kind) > 0 {
+                    attackers |= 1 << (attacker.1 * board::WIDTH + attacker.0);
+                    weight += attack_weight(kind);
+                }
+            }
+        }
+    }
+    let attackers = attackers.count_ones() as usize;
+    let scale = ATTACKERS_SCALE[attackers.min(ATTACKERS_SCALE.len() - 1)];
+    Score::new(-weight * scale / 100, 0)
+}
+
+/// Return the complete king safety score of `side`
+pub fn score(board: &Board, side: Side) -> Score {
+    shield(board, side) + open_files(board, side) + attacks(board, side)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        Board::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn shelter() {
+        let castled = board("6k1/8/8/8/8/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(shield(&castled, Side::White), Score::new(3 * SHIELD_CLOSE, 0));
+        assert_eq!(shield(&castled, Side::Black), Score::default());
+
+        // A pawn that moved up still helps, but less
+        let advanced = board("6k1/8/8/8/8/6P1/5P1P/6K1 w - - 0 1");
+        assert_eq!(shield(&advanced, Side::White), Score::new(2 * SHIELD_CLOSE + SHIELD_FAR, 0));
+
+        // The same for black, and only the files around the king count
+        let black = board("6k1/p4pp1/7p/8/8/8/8/6K1 w - - 0 1");
+        assert_eq!(shield(&black, Side::Black), Score::new(2 * SHIELD_CLOSE + SHIELD_FAR, 0));
+    }
+    #[test]
+    fn files_near_king() {
+        let closed = board("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(open_files(&closed, Side::White), Score::default());
+
+        let open = board("6k1/5p1p/8/8/8/8/5P1P/6K1 w - - 0 1");
+        assert_eq!(open_files(&open, Side::White), Score::new(OPEN_FILE, 0));
+
+        let semi_open = board("6k1/5ppp/8/8/8/8/5P1P/6K1 w - - 0 1");
+        assert_eq!(open_files(&semi_open, Side::White), Score::new(SEMI_OPEN_FILE, 0));
+        assert_eq!(open_files(&semi_open, Side::Black), Score::default());
+
+        // A king in the corner only has two files around it
+        let corner = board("7k/8/8/8/8/8/8/7K w - - 0 1");
+        assert_eq!(open_files(&corner, Side::White), Score::new(2 * OPEN_FILE, 0));
+    }
+    #[test]
+    fn attackers() {
+        // A lone queen isn't an attack yet
+        let queen = board("k7/8/8/8/7q/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(attacks(&queen, Side::White), Score::default());
+
+        // With a knight joining in, half of their weight counts: the queen
+        // hits f2 and h2, the knight g1 and h2
+        let both = board("k7/8/8/8/7q/5n2/5PPP/6K1 w - - 0 1");
+        let weight = 2 * attack_weight(PieceKind::Queen) + 2 * attack_weight(PieceKind::Knight);
+        assert_eq!(attacks(&both, Side::White), Score::new(-weight * ATTACKERS_SCALE[2] / 100, 0));
+        assert_eq!(attacks(&both, Side::Black), Score::default());
+
+        assert_eq!(score(&both, Side::White), shield(&both, Side::White) + attacks(&both, Side::White));
+    }
+}
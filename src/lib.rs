@@ -3,9 +3,24 @@
 use std::fmt;
 
 pub mod board;
+pub mod clock;
+pub mod eval;
+pub mod fen;
+pub mod game;
+pub mod king_safety;
 pub mod minimax;
+pub mod movegen;
+mod ordering;
+pub mod pawns;
+pub mod perft;
+pub mod pgn;
 pub mod piece;
+pub mod pst;
+pub mod san;
+pub mod search;
 pub mod serialize;
+pub mod transposition;
+pub mod zobrist;
 
 // Not really a part of the library, just need this for sharing interface with
 // WASM and binary.
@@ -92,7 +107,7 @@ impl std::str::FromStr for Pos {
 }
 
 /// What side a piece belongs to
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Side {
     Black,
     White
@@ -1,32 +1,139 @@
 use crate::{
-    board::{self, Board},
+    board::{self, Board, Move},
+    eval::{Classic, Evaluator},
+    ordering::MoveOrdering,
     piece::PieceKind,
-    Pos,
+    transposition::{Bound, TranspositionTable},
     Side
 };
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant
+};
+
+/// The score of being checkmated right now. Mates further away are scored
+/// closer to zero, so the shortest mate is preferred.
+pub const MATE: i32 = 100_000;
+/// Scores beyond this are mates
+const MATE_BOUND: i32 = MATE - 1000;
+/// The quiescence search trusts the evaluation this many plies into the search
+/// no matter what, in case checks keep being answered by checks
+const MAX_PLY: u8 = 100;
+
+/// Convert a score relative to the root of the search into one relative to
+/// the position `ply` moves in, for storing in the transposition table
+fn to_table(score: i32, ply: u8) -> i32 {
+    if score > MATE_BOUND {
+        score + ply as i32
+    } else if score < -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+/// The reverse of `to_table`
+fn from_table(score: i32, ply: u8) -> i32 {
+    if score > MATE_BOUND {
+        score - ply as i32
+    } else if score < -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+/// Limits on a single search besides the exit flag, used by `Search`
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Limits {
+    pub deadline: Option<Instant>,
+    /// The maximum number of nodes, including quiescence nodes
+    pub nodes: Option<u64>
+}
+
+/// State shared by the whole search tree
+struct Context<'a> {
+    exit: Option<&'a AtomicBool>,
+    limits: Limits,
+    table: &'a mut TranspositionTable,
+    evaluator: &'a mut dyn Evaluator,
+    /// The keys of all positions leading up to the current one
+    keys: Vec<u64>,
+    /// Set once `exit` was found to be true, after which all scores are junk
+    aborted: bool,
+    /// The best move at the root of the search
+    best: Option<Move>,
+    ordering: &'a mut MoveOrdering,
+    stats: SearchStats,
+    /// The node count at which to look at the time again
+    next_time_check: u64
+}
+impl<'a> Context<'a> {
+    /// Returns true if the board repeats an earlier position. Only the last
+    /// `halfmove_clock` positions can be equal, anything older is separated by
+    /// a capture or pawn move.
+    fn is_repetition(&self, board: &Board) -> bool {
+        let key = board.position_key();
+        self.keys.iter()
+            .rev()
+            .take(board.halfmove_clock as usize)
+            .any(|&other| other == key)
+    }
+    /// Returns true if the search should be given up, and remembers it
+    fn should_stop(&mut self) -> bool {
+        if self.aborted {
+            return true;
+        }
+        let nodes = self.stats.nodes + self.stats.qnodes;
+        // Reading the time isn't free, so only do it every now and then
+        let check_time = nodes >= self.next_time_check;
+        if check_time {
+            self.next_time_check = nodes + 1024;
+        }
+        self.aborted = self.exit.map(|exit| exit.load(Ordering::SeqCst)).unwrap_or(false)
+            || self.limits.nodes.map(|max| nodes >= max).unwrap_or(false)
+            || (check_time && self.limits.deadline.map(|deadline| Instant::now() >= deadline).unwrap_or(false));
+        self.aborted
+    }
+}
+
+/// Statistics about a minimax session
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SearchStats {
+    /// The number of positions visited by the main search
+    pub nodes: u64,
+    /// The number of positions visited by the quiescence search
+    pub qnodes: u64
+}
 
 /// The result of a minimax session
 pub struct MinimaxResult {
+    /// The score relative to the side to move
     pub score: i32,
-    pub from: Pos,
-    pub to: Pos
+    pub move_: Move,
+    /// The depth that was searched
+    pub depth: u8,
+    /// The line of play the search expects, starting with `move_`
+    pub pv: Vec<Move>,
+    pub stats: SearchStats
 }
 
 impl Board {
-    /// Calculate the total score for a certain side
-    pub fn score(&mut self, side: Side) -> i32 {
+    /// Calculate the total score for a certain side. This is what the search
+    /// uses by default, see `eval::Classic`.
+    pub fn score(&self, side: Side) -> i32 {
         let mut score = 0;
         let mut pieces = self.pieces(side);
-        while let Some((from, piece)) = pieces.next(&self) {
+        while let Some((from, piece)) = pieces.next(self) {
             // Material score
             score += piece.kind.worth() as i32 * 100;
 
             // Prioritize moves that reach a lot of position
             // Don't check castlings simply because that's slow
             let mut moves = self.moves_for_filter(from, |m| !board::is_castling(piece, m));
-            while let Some(to) = moves.next(self) {
-                if self.get(to).is_some() {
+            while let Some(m) = moves.next(self) {
+                // Each promotion is generated once per piece, count it once
+                let underpromotion = m.promotion.map(|kind| kind != PieceKind::Queen).unwrap_or(false);
+                if !underpromotion && self.get(m.to).is_some() {
                     score += 1;
                 }
             }
@@ -34,82 +141,326 @@ impl Board {
         score
     }
 
-    /// Return the best move according to a simple minimax algorithm
-    pub fn minimax(&mut self, depth: u8, player: Side, exit: Option<&AtomicBool>) -> Option<MinimaxResult> {
+    /// Return the best move for the side to move according to a simple
+    /// minimax algorithm. This uses a small transposition table that's thrown
+    /// away afterwards, see `minimax_with_table` to keep it between searches.
+    pub fn minimax(&mut self, depth: u8, exit: Option<&AtomicBool>) -> Option<MinimaxResult> {
+        let mut table = TranspositionTable::new(1);
+        self.minimax_with_table(depth, &[], &mut table, exit)
+    }
+    /// Like `minimax`, but reuses and fills the specified transposition table,
+    /// and also scores repetitions of any of the positions in `history`
+    /// (oldest first) as draws
+    pub fn minimax_with_table(
+        &mut self,
+        depth: u8,
+        history: &[u64],
+        table: &mut TranspositionTable,
+        exit: Option<&AtomicBool>
+    ) -> Option<MinimaxResult> {
+        self.minimax_with_evaluator(depth, history, table, &mut Classic, exit)
+    }
+    /// Like `minimax_with_table`, but judges positions using `evaluator`
+    /// instead of `Board::score`
+    pub fn minimax_with_evaluator(
+        &mut self,
+        depth: u8,
+        history: &[u64],
+        table: &mut TranspositionTable,
+        evaluator: &mut dyn Evaluator,
+        exit: Option<&AtomicBool>
+    ) -> Option<MinimaxResult> {
+        let mut ordering = MoveOrdering::new();
+        self.minimax_limited(depth, history, table, &mut ordering, evaluator, exit, Limits::default())
+    }
+    /// Like `minimax_with_evaluator`, but also gives up once any of the
+    /// limits are reached, and keeps the move ordering tables in `ordering`
+    /// so later iterations can use them
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn minimax_limited(
+        &mut self,
+        depth: u8,
+        history: &[u64],
+        table: &mut TranspositionTable,
+        ordering: &mut MoveOrdering,
+        evaluator: &mut dyn Evaluator,
+        exit: Option<&AtomicBool>,
+        limits: Limits
+    ) -> Option<MinimaxResult> {
         assert_ne!(depth, 0, "can't start minimax with 0 depth");
-        self.minimax_inner(depth, player, player, exit, std::i32::MIN, std::i32::MAX)
+        table.new_search();
+        let mut ctx = Context {
+            exit,
+            limits,
+            table,
+            evaluator,
+            keys: history.to_vec(),
+            aborted: false,
+            best: None,
+            ordering,
+            stats: SearchStats::default(),
+            next_time_check: 0
+        };
+        let mut pv = Vec::new();
+        let score = self.minimax_inner(&mut ctx, depth, 0, -i32::MAX, i32::MAX, &mut pv);
+        if ctx.aborted {
+            return None;
+        }
+        let stats = ctx.stats;
+        ctx.best.map(|move_| MinimaxResult {
+            score,
+            move_,
+            depth,
+            pv,
+            stats
+        })
     }
+    /// Return the score of this position relative to the side to move, using
+    /// negamax with alpha-beta pruning. The best line found is written to
+    /// `pv`.
     fn minimax_inner(
         &mut self,
+        ctx: &mut Context,
         depth: u8,
-        original: Side,
-        player: Side,
-        exit: Option<&AtomicBool>,
+        ply: u8,
         mut alpha: i32,
-        mut beta: i32,
-    ) -> Option<MinimaxResult> {
-        let maximizing = original == player;
-        let mut best: Option<MinimaxResult> = None;
-
-        let mut pieces = self.pieces(player);
-        'outer: while let Some((from, _)) = pieces.next(self) {
-            let mut moves = self.moves_for(from);
-            while let Some(to) = moves.next(self) {
-                let game_over = if maximizing { 99999 + depth as i32 } else { -99999 - depth as i32 };
-
-                let score = if let Some(piece) = self.get(to).filter(|p| p.kind == PieceKind::King) {
-                    assert!(player != piece.side);
-                    game_over
-                } else {
-                    // Apply move
-                    let undo = self.move_(from, to);
-
-                    let score = if depth == 1 {
-                        self.score(original) - self.score(!original)
-                    } else {
-                        self.minimax_inner(depth - 1, original, !player, exit, alpha, beta)
-                            .map(|s| s.score)
-                            .unwrap_or(game_over)
-                    };
-
-                    // Undo move
-                    self.undo(undo);
-
-                    score
-                };
-
-                if exit.map(|exit| exit.load(Ordering::SeqCst)).unwrap_or(false) {
-                    return None;
+        beta: i32,
+        pv: &mut Vec<Move>
+    ) -> i32 {
+        pv.clear();
+        if depth == 0 {
+            return self.quiescence(ctx, ply, alpha, beta);
+        }
+        ctx.stats.nodes += 1;
+
+        let player = self.side_to_move;
+
+        let key = self.position_key();
+        let mut hash_move = None;
+        if let Some(entry) = ctx.table.probe(key) {
+            hash_move = entry.best_move;
+            // At the root we need a move, not just a score
+            if ply > 0 && entry.depth >= depth {
+                let score = from_table(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => {
+                        self.table_pv(ctx.table, depth, pv);
+                        return score;
+                    },
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => ()
                 }
+            }
+        }
+
+        let mut moves = self.legal_moves(player);
+        if moves.is_empty() {
+            // No legal moves, this is either a checkmate or a stalemate
+            return if self.check(player).is_some() { -MATE + ply as i32 } else { 0 };
+        }
+        ctx.ordering.order(self, &mut moves, hash_move, ply);
 
-                let this = Some(MinimaxResult {
-                    score,
-                    from,
-                    to
-                });
-                if maximizing && best.as_ref().map(|best| score > best.score).unwrap_or(true) {
-                    best = this;
-                    if score > alpha {
-                        // alpha holds upper half of possible scores
-                        alpha = score;
-                    }
-                } else if !maximizing && best.as_ref().map(|best| score < best.score).unwrap_or(true) {
-                    best = this;
-                    if score < beta {
-                        // beta holds lower half of possible scores
-                        beta = score;
-                    }
+        let original_alpha = alpha;
+        let mut best_score = -i32::MAX;
+        let mut best_move = None;
+
+        ctx.keys.push(key);
+
+        let mut child_pv = Vec::new();
+        for m in moves {
+            // Apply move
+            let undo = self.move_(m);
+
+            // Material can only run out when something is captured, or a pawn
+            // turns into a minor piece
+            let insufficient = (m.is_capture() || m.promotion.is_some()) && self.is_insufficient_material();
+
+            // A mate on the last move before the fifty-move rule still counts,
+            // just like in `Board::outcome`
+            let fifty_moves = self.halfmove_clock >= 100 && {
+                let side = self.side_to_move;
+                self.check(side).is_none() || !self.legal_moves(side).is_empty()
+            };
+
+            child_pv.clear();
+            let score = if fifty_moves || insufficient || ctx.is_repetition(self) {
+                // Draw, no matter what comes next
+                0
+            } else {
+                -self.minimax_inner(ctx, depth - 1, ply + 1, -beta, -alpha, &mut child_pv)
+            };
+
+            // Undo move
+            self.undo(undo);
+
+            if ctx.should_stop() {
+                ctx.keys.pop();
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(m);
+                if score > alpha {
+                    alpha = score;
+
+                    pv.clear();
+                    pv.push(m);
+                    pv.extend_from_slice(&child_pv);
                 }
-                if alpha >= beta {
-                    // This node will not be chosen by the parent node, because
-                    // it has a worse value than a previous node.
-                    // If this is just as confusing to you as it is to me,
-                    // https://youtu.be/xBXHtz4Gbdo might be a good resource.
-                    break 'outer;
+            }
+            if alpha >= beta {
+                // This node will not be chosen by the parent node, because
+                // it has a worse value than a previous node.
+                // If this is just as confusing to you as it is to me,
+                // https://youtu.be/xBXHtz4Gbdo might be a good resource.
+                ctx.ordering.cutoff(player, m, depth, ply);
+                break;
+            }
+        }
+
+        ctx.keys.pop();
+
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        ctx.table.store(key, depth, bound, to_table(best_score, ply), best_move);
+        if ply == 0 {
+            ctx.best = best_move;
+        }
+        best_score
+    }
+    /// Follow the best moves stored in the transposition table for at most
+    /// `depth` moves, to fill in the line below a position that wasn't searched
+    fn table_pv(&mut self, table: &TranspositionTable, depth: u8, pv: &mut Vec<Move>) {
+        if depth == 0 {
+            return;
+        }
+        let m = match table.probe(self.position_key()).and_then(|entry| entry.best_move) {
+            Some(m) => m,
+            None => return
+        };
+        // Two positions can have the same key, so the move might not belong here
+        if self.get(m.from).map(|p| p.side) != Some(self.side_to_move) || !self.is_legal(m) {
+            return;
+        }
+        pv.push(m);
+        let undo = self.move_(m);
+        self.table_pv(table, depth - 1, pv);
+        self.undo(undo);
+    }
+    /// Return the score of this position relative to the side to move, once
+    /// all captures and promotions have been played out. Without this, the
+    /// search would happily take a defended pawn with its queen at the last
+    /// ply.
+    fn quiescence(&mut self, ctx: &mut Context, ply: u8, mut alpha: i32, beta: i32) -> i32 {
+        ctx.stats.qnodes += 1;
+
+        let player = self.side_to_move;
+        let in_check = self.check(player).is_some();
+        let mut best_score = -i32::MAX;
+
+        if !in_check || ply >= MAX_PLY {
+            // Stand pat: the side to move doesn't have to capture anything, so
+            // the position is worth at least its static evaluation
+            best_score = ctx.evaluator.evaluate(self);
+            if best_score >= beta || ply >= MAX_PLY {
+                return best_score;
+            }
+            if best_score > alpha {
+                alpha = best_score;
+            }
+        }
+
+        let mut moves = self.legal_moves(player);
+        if in_check {
+            // Every way out of check is searched, so a mate is a mate
+            if moves.is_empty() {
+                return -MATE + ply as i32;
+            }
+        } else {
+            moves.retain(|m| m.is_capture() || m.promotion == Some(PieceKind::Queen));
+        }
+        ctx.ordering.order(self, &mut moves, None, ply);
+
+        for m in moves {
+            let undo = self.move_(m);
+            let score = if (m.is_capture() || m.promotion.is_some()) && self.is_insufficient_material() {
+                0
+            } else {
+                -self.quiescence(ctx, ply + 1, -beta, -alpha)
+            };
+            self.undo(undo);
+
+            if ctx.should_stop() {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+                if score > alpha {
+                    alpha = score;
                 }
             }
+            if alpha >= beta {
+                break;
+            }
         }
+        best_score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quiescence_recapture() {
+        // Qxd5 wins a pawn at depth 1, unless the search sees cxd5
+        let mut board = Board::from_fen("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1").unwrap();
+        let result = board.minimax(1, None).unwrap();
+        assert_ne!(board.to_san(result.move_), "Qxd5");
+        assert!(result.score < 900, "{} with {}", result.move_, result.score);
+        assert!(result.stats.qnodes > 0);
+    }
+    #[test]
+    fn quiescence_in_check() {
+        // Ra8+ looks like mate if only captures are tried, but Kh7 escapes
+        let mut board = Board::from_fen("6k1/5pp1/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let result = board.minimax(1, None).unwrap();
+        assert!(result.score < MATE_BOUND, "{} with {}", result.move_, result.score);
 
-        best
+        // While with h7 taken it is mate
+        let mut board = Board::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let result = board.minimax(1, None).unwrap();
+        assert_eq!(board.to_san(result.move_), "Ra8#");
+        assert_eq!(result.score, MATE - 1);
+    }
+    #[test]
+    fn principal_variation() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1",
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"
+        ];
+        for &fen in &fens {
+            for depth in 1..=3 {
+                let mut board = Board::from_fen(fen).unwrap();
+                let result = board.minimax(depth, None).unwrap();
+                assert_eq!(result.pv.first(), Some(&result.move_), "{} at depth {}", fen, depth);
+                // Quiescence moves aren't part of it
+                assert!(result.pv.len() <= depth as usize, "{} at depth {}", fen, depth);
+                for &m in &result.pv {
+                    assert!(board.is_legal(m), "{} in the line of {} at depth {}", m, fen, depth);
+                    board.move_(m);
+                }
+            }
+        }
     }
 }
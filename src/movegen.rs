@@ -0,0 +1,415 @@
+use arrayvec::ArrayVec;
+use crate::{
+    board::{self, Board, Move, MoveFlags},
+    piece::{Piece, PieceKind},
+    Pos,
+    Side
+};
+
+/// A list of moves, large enough to hold every legal move of any position
+pub type MoveList = ArrayVec<[Move; 256]>;
+
+const ROOK_DIRECTIONS: [Pos; 4] = [Pos(0, 1), Pos(0, -1), Pos(1, 0), Pos(-1, 0)];
+const BISHOP_DIRECTIONS: [Pos; 4] = [Pos(1, 1), Pos(1, -1), Pos(-1, 1), Pos(-1, -1)];
+const KING_OFFSETS: [Pos; 8] = [
+    Pos(0, 1), Pos(0, -1), Pos(1, 0), Pos(-1, 0),
+    Pos(1, 1), Pos(1, -1), Pos(-1, 1), Pos(-1, -1)
+];
+const KNIGHT_OFFSETS: [Pos; 8] = [
+    Pos(1, 2), Pos(1, -2), Pos(-1, 2), Pos(-1, -2),
+    Pos(2, 1), Pos(2, -1), Pos(-2, 1), Pos(-2, -1)
+];
+const PROMOTIONS: [PieceKind; 4] = [PieceKind::Queen, PieceKind::Rook, PieceKind::Bishop, PieceKind::Knight];
+
+/// Returns true if a piece of this kind slides in the specified direction
+fn slides(kind: PieceKind, dir: Pos) -> bool {
+    let diagonal = dir.0 != 0 && dir.1 != 0;
+    match kind {
+        PieceKind::Queen => true,
+        PieceKind::Rook => !diagonal,
+        PieceKind::Bishop => diagonal,
+        _ => false
+    }
+}
+/// Returns true if `pos` lies on the ray going from `start` in the direction `dir`
+fn on_ray(start: Pos, dir: Pos, pos: Pos) -> bool {
+    let Pos(dx, dy) = pos - start;
+    let Pos(dir_x, dir_y) = dir;
+    let steps = if dir_x != 0 { dx / dir_x } else { dy / dir_y };
+    steps > 0 && Pos(dir_x * steps, dir_y * steps) == Pos(dx, dy)
+}
+
+/// What the side to generate moves for must respect to not end up in check
+struct Restrictions {
+    king: Option<Pos>,
+    checkers: ArrayVec<[Pos; 16]>,
+    /// Positions a piece can move to in order to stop a single check
+    block: ArrayVec<[Pos; 8]>,
+    /// Pinned pieces and the direction from the king they're pinned in
+    pins: ArrayVec<[(Pos, Pos); 8]>
+}
+impl Restrictions {
+    /// Returns true if a piece other than the king may move from `from` to
+    /// `to` without leaving the king in check
+    fn allows(&self, from: Pos, to: Pos) -> bool {
+        if !self.checkers.is_empty() && !self.block.contains(&to) {
+            return false;
+        }
+        match self.pins.iter().find(|&&(pos, _)| pos == from) {
+            Some(&(_, dir)) => on_ray(self.king.unwrap(), dir, to),
+            None => true
+        }
+    }
+}
+
+impl Board {
+    /// Find the king of the specified side, if any
+    pub fn king(&self, side: Side) -> Option<Pos> {
+        let mut pieces = self.pieces(side);
+        while let Some((pos, piece)) = pieces.next(self) {
+            if piece.kind == PieceKind::King {
+                return Some(pos);
+            }
+        }
+        None
+    }
+    /// Returns true if `pos` is attacked by any piece belonging to `side`
+    pub fn is_attacked(&self, pos: Pos, side: Side) -> bool {
+        self.is_attacked_with(pos, side, |pos| self.get(pos))
+    }
+    /// Return the position of all pieces belonging to `side` attacking `pos`
+    pub fn attackers(&self, pos: Pos, side: Side) -> ArrayVec<[Pos; 16]> {
+        let mut attackers = ArrayVec::new();
+        self.for_each_attacker(pos, side, |pos| self.get(pos), |attacker| {
+            attackers.push(attacker);
+            false
+        });
+        attackers
+    }
+    /// Like `is_attacked`, but looks at the board through `get`, allowing
+    /// callers to see what would happen if pieces were moved.
+    fn is_attacked_with<G>(&self, pos: Pos, side: Side, get: G) -> bool
+        where G: Fn(Pos) -> Option<Piece>
+    {
+        let mut attacked = false;
+        self.for_each_attacker(pos, side, get, |_| {
+            attacked = true;
+            true
+        });
+        attacked
+    }
+    /// Run `f` for each attacker of `pos` belonging to `side`, until it
+    /// returns true.
+    fn for_each_attacker<G, F>(&self, pos: Pos, side: Side, get: G, mut f: F)
+        where G: Fn(Pos) -> Option<Piece>,
+              F: FnMut(Pos) -> bool
+    {
+        let is = |pos: Pos, kind: PieceKind| pos.is_valid() && get(pos) == Some(Piece { kind, side });
+
+        let behind = pos - board::forward(side);
+        for &pawn in &[behind + Pos(1, 0), behind + Pos(-1, 0)] {
+            if is(pawn, PieceKind::Pawn) && f(pawn) {
+                return;
+            }
+        }
+        for &offset in &KNIGHT_OFFSETS {
+            if is(pos + offset, PieceKind::Knight) && f(pos + offset) {
+                return;
+            }
+        }
+        for &offset in &KING_OFFSETS {
+            if is(pos + offset, PieceKind::King) && f(pos + offset) {
+                return;
+            }
+        }
+        for &dir in ROOK_DIRECTIONS.iter().chain(&BISHOP_DIRECTIONS) {
+            let mut cursor = pos + dir;
+            while cursor.is_valid() {
+                if let Some(piece) = get(cursor) {
+                    if piece.side == side && slides(piece.kind, dir) && f(cursor) {
+                        return;
+                    }
+                    break;
+                }
+                cursor += dir;
+            }
+        }
+    }
+
+    /// Return all fully legal moves the specified side can make
+    pub fn legal_moves(&self, side: Side) -> MoveList {
+        let restrictions = self.restrictions(side);
+        let mut list = MoveList::new();
+        let mut pieces = self.pieces(side);
+        while let Some((pos, piece)) = pieces.next(self) {
+            self.generate(pos, piece, &restrictions, &mut list);
+        }
+        list
+    }
+    /// Return all fully legal moves the piece at `pos` can make
+    pub fn legal_moves_from(&self, pos: Pos) -> MoveList {
+        let mut list = MoveList::new();
+        if let Some(piece) = self.get(pos) {
+            let restrictions = self.restrictions(piece.side);
+            self.generate(pos, piece, &restrictions, &mut list);
+        }
+        list
+    }
+    /// Returns true if `m` is a fully legal move for the piece on `m.from`
+    pub fn is_legal(&self, m: Move) -> bool {
+        self.legal_moves_from(m.from).contains(&m)
+    }
+
+    fn restrictions(&self, side: Side) -> Restrictions {
+        let mut restrictions = Restrictions {
+            king: self.king(side),
+            checkers: ArrayVec::new(),
+            block: ArrayVec::new(),
+            pins: ArrayVec::new()
+        };
+        let king = match restrictions.king {
+            Some(king) => king,
+            None => return restrictions
+        };
+
+        restrictions.checkers = self.attackers(king, !side);
+        if let [checker] = *restrictions.checkers {
+            restrictions.block.push(checker);
+        }
+
+        for &dir in ROOK_DIRECTIONS.iter().chain(&BISHOP_DIRECTIONS) {
+            let mut cursor = king + dir;
+            let mut own = None;
+            while cursor.is_valid() {
+                if let Some(piece) = self.get(cursor) {
+                    if piece.side == side {
+                        if own.is_some() {
+                            break;
+                        }
+                        own = Some(cursor);
+                    } else {
+                        if slides(piece.kind, dir) {
+                            match own {
+                                Some(pinned) => restrictions.pins.push((pinned, dir)),
+                                None => if restrictions.checkers.len() == 1 {
+                                    // Sliding checker, may also be blocked
+                                    let mut block = king + dir;
+                                    while block != cursor {
+                                        restrictions.block.push(block);
+                                        block += dir;
+                                    }
+                                }
+                            }
+                        }
+                        break;
+                    }
+                }
+                cursor += dir;
+            }
+        }
+        restrictions
+    }
+
+    fn generate(&self, from: Pos, piece: Piece, restrictions: &Restrictions, list: &mut MoveList) {
+        if piece.kind == PieceKind::King {
+            self.generate_king(from, piece, restrictions, list);
+            return;
+        }
+        if restrictions.checkers.len() > 1 {
+            // Double check, only the king can move
+            return;
+        }
+        if piece.kind == PieceKind::Pawn {
+            self.generate_pawn(from, piece, restrictions, list);
+            return;
+        }
+
+        let mut push = |to: Pos, flags: MoveFlags| {
+            if restrictions.allows(from, to) {
+                list.push(Move { from, to, promotion: None, flags });
+            }
+        };
+
+        match piece.kind {
+            PieceKind::Knight => for &offset in &KNIGHT_OFFSETS {
+                let to = from + offset;
+                if to.is_valid() {
+                    match self.get(to) {
+                        None => push(to, MoveFlags::default()),
+                        Some(other) if other.side != piece.side => push(to, MoveFlags::CAPTURE),
+                        Some(_) => ()
+                    }
+                }
+            },
+            _ => for &dir in ROOK_DIRECTIONS.iter().chain(&BISHOP_DIRECTIONS) {
+                if !slides(piece.kind, dir) {
+                    continue;
+                }
+                let mut to = from + dir;
+                while to.is_valid() {
+                    match self.get(to) {
+                        None => push(to, MoveFlags::default()),
+                        Some(other) => {
+                            if other.side != piece.side {
+                                push(to, MoveFlags::CAPTURE);
+                            }
+                            break;
+                        }
+                    }
+                    to += dir;
+                }
+            }
+        }
+    }
+    fn generate_pawn(&self, from: Pos, piece: Piece, restrictions: &Restrictions, list: &mut MoveList) {
+        let side = piece.side;
+        let dir = board::forward(side);
+        let mut push = |to: Pos, flags: MoveFlags| {
+            if !restrictions.allows(from, to) {
+                return;
+            }
+            if to.1 == board::edge_offset(!side, 0) {
+                for &kind in &PROMOTIONS {
+                    list.push(Move { from, to, promotion: Some(kind), flags });
+                }
+            } else {
+                list.push(Move { from, to, promotion: None, flags });
+            }
+        };
+
+        let one = from + dir;
+        if one.is_valid() && self.get(one).is_none() {
+            push(one, MoveFlags::default());
+
+            let two = one + dir;
+            if from.1 == board::edge_offset(side, 1) && self.get(two).is_none() {
+                push(two, MoveFlags::DOUBLE_PUSH);
+            }
+        }
+
+        let mut en_passant = None;
+        for &to in &[one + Pos(1, 0), one + Pos(-1, 0)] {
+            if !to.is_valid() {
+                continue;
+            }
+            match self.get(to) {
+                Some(other) => if other.side != side {
+                    push(to, MoveFlags::CAPTURE);
+                },
+                None => {
+                    let victim = Pos(to.0, from.1);
+                    if self.en_passant != Some(victim)
+                            || self.get(victim) != Some(Piece { kind: PieceKind::Pawn, side: !side }) {
+                        continue;
+                    }
+                    // En passant removes two pieces from the same row, which
+                    // can expose the king in ways the pin detection doesn't
+                    // see. This is rare enough to just look at the result.
+                    let exposed = restrictions.king.map(|king| {
+                        self.is_attacked_with(king, !side, |pos| {
+                            if pos == from || pos == victim {
+                                None
+                            } else if pos == to {
+                                Some(piece)
+                            } else {
+                                self.get(pos)
+                            }
+                        })
+                    }).unwrap_or(false);
+                    if !exposed {
+                        en_passant = Some(Move {
+                            from,
+                            to,
+                            promotion: None,
+                            flags: MoveFlags::CAPTURE | MoveFlags::EN_PASSANT
+                        });
+                    }
+                }
+            }
+        }
+        list.extend(en_passant);
+    }
+    fn generate_king(&self, from: Pos, piece: Piece, restrictions: &Restrictions, list: &mut MoveList) {
+        let side = piece.side;
+        // Sliding pieces can still attack squares behind the king
+        let without_king = |pos: Pos| if pos == from { None } else { self.get(pos) };
+
+        for &offset in &KING_OFFSETS {
+            let to = from + offset;
+            if !to.is_valid() {
+                continue;
+            }
+            let flags = match self.get(to) {
+                None => MoveFlags::default(),
+                Some(other) if other.side != side => MoveFlags::CAPTURE,
+                Some(_) => continue
+            };
+            if !self.is_attacked_with(to, !side, without_king) {
+                list.push(Move { from, to, promotion: None, flags });
+            }
+        }
+
+        // The castling rights only make sense with the king and rook on their
+        // starting squares, which a hand-edited position might not have
+        let row = board::edge_offset(side, 0);
+        let Pos(from_x, _) = from;
+        if !restrictions.checkers.is_empty() || from != Pos(4, row) {
+            return;
+        }
+        let castling = self.castling_rights(side);
+        let rook = Some(Piece { kind: PieceKind::Rook, side });
+        for &(allowed, rook_x, dir) in &[(castling.kingside, board::WIDTH - 1, 1), (castling.queenside, 0, -1)] {
+            if !allowed || self.get(Pos(rook_x, row)) != rook {
+                continue;
+            }
+            let mut x = from_x + dir;
+            let mut empty = true;
+            while x != rook_x {
+                empty = empty && self.get(Pos(x, row)).is_none();
+                x += dir;
+            }
+            if empty
+                    && !self.is_attacked(Pos(from_x + dir, row), !side)
+                    && !self.is_attacked(Pos(from_x + 2 * dir, row), !side) {
+                list.push(Move {
+                    from,
+                    to: Pos(from_x + 2 * dir, row),
+                    promotion: None,
+                    flags: MoveFlags::CASTLING
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Castling;
+
+    /// Parse a position, then grant White castling rights regardless of
+    /// where its king and rook are
+    fn with_rights(fen: &str) -> Board {
+        let mut board = Board::from_fen(fen).unwrap();
+        board.castling_white = Castling::default();
+        board.zobrist = board.compute_zobrist();
+        board
+    }
+
+    #[test]
+    fn castling_needs_home_squares() {
+        for &fen in &["4k3/8/8/8/8/8/8/6KR w - - 0 1", "4k3/8/8/8/8/8/8/RK6 w - - 0 1"] {
+            let mut board = with_rights(fen);
+            let moves = board.legal_moves(Side::White);
+            assert!(moves.iter().all(|m| m.to.is_valid() && !m.is_castling()), "{}: {:?}", fen, moves);
+            assert_eq!(board.perft(1), moves.len() as u64);
+            board.perft(3);
+        }
+
+        // Kiwipete, but with the king moved off the e-file
+        let board = with_rights("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R2K3R w kq - 0 1");
+        assert!(board.legal_moves(Side::White).iter().all(|m| !m.is_castling()));
+        let board = with_rights("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w kq - 0 1");
+        assert_eq!(board.legal_moves(Side::White).iter().filter(|m| m.is_castling()).count(), 2);
+    }
+}
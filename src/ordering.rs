@@ -0,0 +1,167 @@
+use crate::{
+    board::{Board, Move, MoveFlags, WIDTH},
+    movegen::MoveList,
+    piece::PieceKind,
+    Pos,
+    Side
+};
+use std::cmp::Reverse;
+
+/// How many quiet moves that caused a cutoff are remembered per ply
+const KILLERS: usize = 2;
+
+// Moves are sorted by tier first, then by the score within the tier
+const TIER_HASH: u64 = 4;
+const TIER_CAPTURE: u64 = 3;
+const TIER_KILLER: u64 = 2;
+const TIER_QUIET: u64 = 1;
+
+const SQUARES: usize = (WIDTH * WIDTH) as usize;
+
+fn index(Pos(x, y): Pos) -> usize {
+    (y * WIDTH + x) as usize
+}
+
+/// Remembers which moves were good in earlier parts of the search, to try
+/// similar moves first elsewhere
+pub(crate) struct MoveOrdering {
+    /// Quiet moves that caused a beta cutoff, per ply
+    killers: Vec<[Option<Move>; KILLERS]>,
+    /// How often a quiet move caused a cutoff, weighted by depth, indexed by
+    /// side, from and to
+    history: Vec<[[u32; SQUARES]; SQUARES]>
+}
+impl MoveOrdering {
+    pub(crate) fn new() -> Self {
+        Self {
+            killers: Vec::new(),
+            history: vec![[[0; SQUARES]; SQUARES]; 2]
+        }
+    }
+    /// Mark the start of a new search. Killer moves are forgotten since they
+    /// belong to plies of the old search, and the history is halved so it
+    /// slowly adapts to the new position.
+    pub(crate) fn new_search(&mut self) {
+        self.killers.clear();
+        for from in self.history.iter_mut().flat_map(|side| side.iter_mut()) {
+            for entry in from.iter_mut() {
+                *entry /= 2;
+            }
+        }
+    }
+    fn history(&self, side: Side, m: Move) -> u32 {
+        self.history[side as usize][index(m.from)][index(m.to)]
+    }
+    /// Sort the moves so the most promising are tried first: the hash move,
+    /// then captures and promotions by most valuable victim and least valuable
+    /// attacker, then killer moves, then other quiet moves by history
+    pub(crate) fn order(&self, board: &Board, moves: &mut MoveList, hash_move: Option<Move>, ply: u8) {
+        let side = board.side_to_move();
+        let killers = self.killers.get(ply as usize);
+        moves.sort_by_key(|&m| {
+            let (tier, score) = if Some(m) == hash_move {
+                (TIER_HASH, 0)
+            } else if m.is_capture() || m.promotion.is_some() {
+                let victim = if m.flags.contains(MoveFlags::EN_PASSANT) {
+                    PieceKind::Pawn.worth()
+                } else {
+                    board.get(m.to).map(|p| p.kind.worth()).unwrap_or(0)
+                };
+                let promotion = m.promotion.map(|kind| kind.worth()).unwrap_or(0);
+                let attacker = board.get(m.from).map(|p| p.kind.worth()).unwrap_or(0);
+                (TIER_CAPTURE, (victim as u64 + promotion as u64) * 16 - attacker as u64)
+            } else if let Some(i) = killers.and_then(|killers| killers.iter().position(|&k| k == Some(m))) {
+                (TIER_KILLER, (KILLERS - i) as u64)
+            } else {
+                (TIER_QUIET, self.history(side, m) as u64)
+            };
+            Reverse(tier << 32 | score)
+        });
+    }
+    /// Remember a move that caused a beta cutoff. Captures are already tried
+    /// early, so only quiet moves are remembered.
+    pub(crate) fn cutoff(&mut self, side: Side, m: Move, depth: u8, ply: u8) {
+        if m.is_capture() || m.promotion.is_some() {
+            return;
+        }
+        let ply = ply as usize;
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None; KILLERS]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(m) {
+            killers.rotate_right(1);
+            killers[0] = Some(m);
+        }
+
+        let entry = &mut self.history[side as usize][index(m.from)][index(m.to)];
+        *entry = entry.saturating_add(depth as u32 * depth as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn order() {
+        let board = Board::from_fen("4k3/8/2p1n3/3P4/8/8/8/R3K3 w - - 0 1").unwrap();
+        let m = |san| board.parse_move(san).unwrap();
+
+        let mut ordering = MoveOrdering::new();
+        ordering.cutoff(Side::White, m("Ra3"), 1, 0);
+        // A better history, but at another ply, so not a killer here
+        ordering.cutoff(Side::White, m("Ra7"), 4, 5);
+
+        let mut moves = board.legal_moves(Side::White);
+        ordering.order(&board, &mut moves, Some(m("Ke2")), 0);
+        assert_eq!(&moves[..5], &[m("Ke2"), m("dxe6"), m("dxc6"), m("Ra3"), m("Ra7")]);
+
+        // Without a hash move the captures come first
+        let mut moves = board.legal_moves(Side::White);
+        ordering.order(&board, &mut moves, None, 0);
+        assert_eq!(&moves[..4], &[m("dxe6"), m("dxc6"), m("Ra3"), m("Ra7")]);
+    }
+    #[test]
+    fn killers() {
+        let board = Board::default();
+        let (a, b, c) = (board.parse_move("e4").unwrap(), board.parse_move("d4").unwrap(), board.parse_move("Nf3").unwrap());
+        let capture = Board::from_fen("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1").unwrap().parse_move("exd5").unwrap();
+
+        let mut ordering = MoveOrdering::new();
+        ordering.cutoff(Side::White, a, 1, 2);
+        ordering.cutoff(Side::White, a, 1, 2);
+        assert_eq!(ordering.killers[2], [Some(a), None]);
+        ordering.cutoff(Side::White, b, 1, 2);
+        assert_eq!(ordering.killers[2], [Some(b), Some(a)]);
+        ordering.cutoff(Side::White, c, 1, 2);
+        assert_eq!(ordering.killers[2], [Some(c), Some(b)]);
+
+        // Other plies have their own
+        assert_eq!(ordering.killers[0], [None, None]);
+        ordering.cutoff(Side::White, a, 1, 0);
+        assert_eq!(ordering.killers[0], [Some(a), None]);
+        assert_eq!(ordering.killers[2], [Some(c), Some(b)]);
+
+        // Captures are tried early anyway
+        ordering.cutoff(Side::White, capture, 1, 0);
+        assert_eq!(ordering.killers[0], [Some(a), None]);
+        assert_eq!(ordering.history(Side::White, capture), 0);
+    }
+    #[test]
+    fn new_search() {
+        let board = Board::default();
+        let m = board.parse_move("e4").unwrap();
+
+        let mut ordering = MoveOrdering::new();
+        ordering.cutoff(Side::White, m, 4, 1);
+        assert_eq!(ordering.history(Side::White, m), 16);
+        assert_eq!(ordering.history(Side::Black, m), 0);
+
+        ordering.new_search();
+        assert_eq!(ordering.history(Side::White, m), 8);
+        assert!(ordering.killers.is_empty());
+        ordering.new_search();
+        assert_eq!(ordering.history(Side::White, m), 4);
+    }
+}
@@ -0,0 +1,304 @@
+// Pawn structure evaluation. Pawns move rarely and never sideways without
+// capturing, so the same pawn structure shows up over and over in a search.
+// Everything that only depends on the pawns is computed once per structure
+// and cached by the pawn key, see `Board::pawn_key`.
+
+use crate::{
+    board::{self, Board},
+    eval::Score,
+    piece::{Piece, PieceKind},
+    Pos,
+    Side
+};
+use std::mem;
+
+/// The size of a pawn hash table in megabytes when none is specified
+pub const DEFAULT_SIZE: usize = 1;
+
+/// Penalty for every pawn on a file beyond the first
+const DOUBLED: Score = Score { mg: -10, eg: -25 };
+/// Penalty for a pawn with no friendly pawns on the files next to it
+const ISOLATED: Score = Score { mg: -10, eg: -15 };
+/// Penalty for a pawn that fell behind its neighbours and can't safely
+/// advance to catch up
+const BACKWARD: Score = Score { mg: -8, eg: -10 };
+/// Bonus for a passed pawn, by how many ranks it has advanced
+const PASSED_MG: [i32; 8] = [0, 5, 10, 20, 35, 60, 100, 0];
+const PASSED_EG: [i32; 8] = [0, 10, 20, 40, 70, 120, 200, 0];
+
+/// Return how many ranks a pawn of the specified side on row `y` is from its
+/// own edge of the board
+fn rank(side: Side, y: i8) -> usize {
+    board::edge_offset(side, y) as usize
+}
+fn bit(Pos(x, y): Pos) -> u64 {
+    1 << (y * board::WIDTH + x)
+}
+
+/// The cached analysis of one pawn structure
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PawnEntry {
+    pub key: u64,
+    white: Score,
+    black: Score,
+    /// A bit for every passed pawn, at `y * WIDTH + x`
+    passed: u64
+}
+impl PawnEntry {
+    /// Analyze the pawn structure of a board from scratch
+    pub fn new(board: &Board) -> Self {
+        let mut entry = Self {
+            key: board.pawn_key(),
+            ..Self::default()
+        };
+        for &side in &[Side::White, Side::Black] {
+            let (score, passed) = analyze(board, side);
+            match side {
+                Side::White => entry.white = score,
+                Side::Black => entry.black = score
+            }
+            entry.passed |= passed;
+        }
+        entry
+    }
+    /// Return the score for the doubled, isolated and backward pawns of one
+    /// side. This is not including passed pawns, see `passed`.
+    pub fn structure(&self, side: Side) -> Score {
+        match side {
+            Side::White => self.white,
+            Side::Black => self.black
+        }
+    }
+    /// Return whether the pawn on `pos` is passed
+    pub fn is_passed(&self, pos: Pos) -> bool {
+        self.passed & bit(pos) != 0
+    }
+    /// Return the bonus for the passed pawns of one side. This depends on
+    /// more than the pawns, so it's computed anew every time: a passed pawn
+    /// with a piece in front of it is worth half as much.
+    pub fn passed(&self, board: &Board, side: Side) -> Score {
+        let mut score = Score::default();
+        let mut pieces = board.pieces(side);
+        while let Some((pos, piece)) = pieces.next(board) {
+            if piece.kind != PieceKind::Pawn || !self.is_passed(pos) {
+                continue;
+            }
+            let rank = rank(side, pos.1);
+            let mut bonus = Score::new(PASSED_MG[rank], PASSED_EG[rank]);
+            let stop = pos + board::forward(side);
+            if stop.is_valid() && board.get(stop).is_some() {
+                bonus = Score::new(bonus.mg / 2, bonus.eg / 2);
+            }
+            score += bonus;
+        }
+        score
+    }
+    /// Return the complete pawn score of one side
+    pub fn score(&self, board: &Board, side: Side) -> Score {
+        self.structure(side) + self.passed(board, side)
+    }
+}
+
+/// Return the structure score of one side and its passed pawns
+fn analyze(board: &Board, side: Side) -> (Score, u64) {
+    let own = Piece { kind: PieceKind::Pawn, side };
+    let enemy = Piece { kind: PieceKind::Pawn, side: !side };
+    let is = |pos: Pos, pawn: Piece| pos.is_valid() && board.get(pos) == Some(pawn);
+
+    // The ranks of the pawns on each file, relative to their own side
+    let mut files = [[false; board::WIDTH as usize]; board::WIDTH as usize];
+    let mut enemy_files = [[false; board::WIDTH as usize]; board::WIDTH as usize];
+    for (y, row) in board.iter().enumerate() {
+        for (x, &piece) in row.iter().enumerate() {
+            if piece == Some(own) {
+                files[x][rank(side, y as i8)] = true;
+            } else if piece == Some(enemy) {
+                enemy_files[x][rank(side, y as i8)] = true;
+            }
+        }
+    }
+    let neighbours = |x: i8| (x - 1..=x + 1).filter(|x| (0..board::WIDTH).contains(x)).map(|x| x as usize);
+
+    let mut score = Score::default();
+    let mut passed = 0;
+    for x in 0..board::WIDTH {
+        let count = files[x as usize].iter().filter(|&&pawn| pawn).count() as i32;
+        if count > 1 {
+            score += Score::new(DOUBLED.mg * (count - 1), DOUBLED.eg * (count - 1));
+        }
+        let isolated = neighbours(x).all(|file| file == x as usize || !files[file].contains(&true));
+
+        for r in (0..board::WIDTH as usize).filter(|&r| files[x as usize][r]) {
+            let y = board::edge_offset(side, r as i8);
+            let pos = Pos(x, y);
+
+            if isolated {
+                score += ISOLATED;
+            } else {
+                // No friendly pawn beside or behind it can ever defend it,
+                // and an enemy pawn stops it from advancing
+                let unsupported = neighbours(x)
+                    .filter(|&file| file != x as usize)
+                    .all(|file| !files[file][..=r].contains(&true));
+                let stop = pos + board::forward(side);
+                let attacked = [Pos(-1, 0), Pos(1, 0)].iter()
+                    .any(|&dx| is(stop + board::forward(side) + dx, enemy));
+                if unsupported && attacked {
+                    score += BACKWARD;
+                }
+            }
+
+            // No enemy pawn can stop it, and it isn't stuck behind one of
+            // its own
+            let ahead = r + 1..board::WIDTH as usize;
+            let free = neighbours(x).all(|file| !enemy_files[file][ahead.clone()].contains(&true))
+                && !files[x as usize][ahead].contains(&true);
+            if free {
+                passed |= bit(pos);
+            }
+        }
+    }
+    (score, passed)
+}
+
+/// A fixed-size hash table of analyzed pawn structures, indexed by pawn key.
+/// Unlike a transposition table it's never wrong to keep entries around, so
+/// it can be shared between searches and even games.
+#[derive(Clone, Debug)]
+pub struct PawnTable {
+    entries: Vec<Option<PawnEntry>>
+}
+impl Default for PawnTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIZE)
+    }
+}
+impl PawnTable {
+    /// Create a table taking up roughly the specified number of megabytes. A
+    /// table of 0 megabytes never stores anything.
+    pub fn new(megabytes: usize) -> Self {
+        let len = megabytes * 1024 * 1024 / mem::size_of::<Option<PawnEntry>>();
+        Self {
+            entries: vec![None; len]
+        }
+    }
+    /// Forget all stored pawn structures
+    pub fn clear(&mut self) {
+        for entry in &mut self.entries {
+            *entry = None;
+        }
+    }
+    /// Return the number of pawn structures the table can hold
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+    /// Return the analysis of the board's pawn structure, computing and
+    /// storing it if it isn't in the table
+    pub fn get(&mut self, board: &Board) -> PawnEntry {
+        let key = board.pawn_key();
+        if self.entries.is_empty() {
+            return PawnEntry::new(board);
+        }
+        let index = (key % self.entries.len() as u64) as usize;
+        match self.entries[index] {
+            Some(entry) if entry.key == key => entry,
+            _ => {
+                let entry = PawnEntry::new(board);
+                self.entries[index] = Some(entry);
+                entry
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(fen: &str) -> (Board, PawnEntry) {
+        let board = Board::from_fen(fen).unwrap();
+        let entry = PawnEntry::new(&board);
+        (board, entry)
+    }
+
+    #[test]
+    fn doubled_and_isolated() {
+        // Two pawns next to each other support each other
+        let (_, pawns) = entry("4k3/8/8/8/8/8/3PP3/4K3 w - - 0 1");
+        assert_eq!(pawns.structure(Side::White), Score::default());
+
+        // Every pawn without neighbours counts
+        let (_, pawns) = entry("4k3/8/8/8/8/8/P1P5/4K3 w - - 0 1");
+        assert_eq!(pawns.structure(Side::White), ISOLATED + ISOLATED);
+
+        // Doubled and isolated at once
+        let (_, pawns) = entry("4k3/8/8/8/8/4P3/4P3/4K3 w - - 0 1");
+        assert_eq!(pawns.structure(Side::White), DOUBLED + ISOLATED + ISOLATED);
+        assert_eq!(pawns.structure(Side::Black), Score::default());
+
+        // The same for black
+        let (_, pawns) = entry("4k3/4p3/4p3/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(pawns.structure(Side::Black), DOUBLED + ISOLATED + ISOLATED);
+    }
+    #[test]
+    fn backward() {
+        // d2 can't advance without being taken by e4, and c3 is ahead of it
+        let (_, pawns) = entry("4k3/8/8/8/4p3/2P5/3P4/4K3 w - - 0 1");
+        assert_eq!(pawns.structure(Side::White), BACKWARD);
+        // With c2 instead it's defended
+        let (_, pawns) = entry("4k3/8/8/8/4p3/8/2PP4/4K3 w - - 0 1");
+        assert_eq!(pawns.structure(Side::White), Score::default());
+    }
+    #[test]
+    fn passed() {
+        // d7 stops e5 from passing, and e5 stops d7
+        let (board, pawns) = entry("4k3/3p4/8/4P3/8/8/8/4K3 w - - 0 1");
+        assert!(!pawns.is_passed(Pos(4, 3)));
+        assert!(!pawns.is_passed(Pos(3, 1)));
+        assert_eq!(pawns.passed(&board, Side::White), Score::default());
+
+        // Nothing stops either of these
+        let (board, pawns) = entry("4k3/p7/8/4P3/8/8/8/4K3 w - - 0 1");
+        assert!(pawns.is_passed(Pos(4, 3)));
+        assert!(pawns.is_passed(Pos(0, 1)));
+        assert_eq!(pawns.passed(&board, Side::White), Score::new(PASSED_MG[4], PASSED_EG[4]));
+        assert_eq!(pawns.passed(&board, Side::Black), Score::new(PASSED_MG[1], PASSED_EG[1]));
+        assert_eq!(pawns.score(&board, Side::White), ISOLATED + Score::new(PASSED_MG[4], PASSED_EG[4]));
+
+        // Blockading it halves the bonus
+        let (board, pawns) = entry("8/p7/4k3/4P3/8/8/8/4K3 w - - 0 1");
+        assert!(pawns.is_passed(Pos(4, 3)));
+        assert_eq!(pawns.passed(&board, Side::White), Score::new(PASSED_MG[4] / 2, PASSED_EG[4] / 2));
+
+        // The rear one of two doubled pawns isn't passed
+        let (_, pawns) = entry("4k3/8/8/4P3/4P3/8/8/4K3 w - - 0 1");
+        assert!(pawns.is_passed(Pos(4, 3)));
+        assert!(!pawns.is_passed(Pos(4, 4)));
+    }
+    #[test]
+    fn table() {
+        let a = Board::from_fen("4k3/p7/8/4P3/8/8/8/4K3 w - - 0 1").unwrap();
+        let b = Board::from_fen("4k3/8/8/8/8/4P3/4P3/4K3 w - - 0 1").unwrap();
+
+        // A hit returns what's stored without analyzing the pawns again
+        let mut table = PawnTable { entries: vec![None; 1] };
+        let mut planted = PawnEntry::new(&a);
+        planted.white = Score::new(1, 2);
+        table.entries[0] = Some(planted);
+        assert_eq!(table.get(&a), planted);
+
+        // Another structure replaces it
+        assert_eq!(table.get(&b), PawnEntry::new(&b));
+        assert_eq!(table.entries[0].map(|entry| entry.key), Some(b.pawn_key()));
+        assert_eq!(table.get(&a), PawnEntry::new(&a));
+
+        table.clear();
+        assert_eq!(table.entries[0], None);
+
+        // A table without room still works
+        let mut table = PawnTable::new(0);
+        assert_eq!(table.capacity(), 0);
+        assert_eq!(table.get(&a), PawnEntry::new(&a));
+        assert!(PawnTable::new(1).capacity() > 0);
+    }
+}
@@ -0,0 +1,36 @@
+use crate::board::{Board, Move};
+
+impl Board {
+    /// Count all positions reachable in exactly `depth` moves, to verify the
+    /// move generator against known numbers
+    pub fn perft(&mut self, depth: u8) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.legal_moves(self.side_to_move());
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        let mut nodes = 0;
+        for m in moves {
+            let change = self.move_(m);
+            nodes += self.perft(depth - 1);
+            self.undo(change);
+        }
+        nodes
+    }
+    /// Like `perft`, but split up by the first move, which helps finding out
+    /// where the move generator goes wrong. `depth` must be at least 1.
+    pub fn divide(&mut self, depth: u8) -> Vec<(Move, u64)> {
+        assert!(depth >= 1, "can't divide perft at depth 0");
+        self.legal_moves(self.side_to_move())
+            .into_iter()
+            .map(|m| {
+                let change = self.move_(m);
+                let nodes = self.perft(depth - 1);
+                self.undo(change);
+                (m, nodes)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,813 @@
+use crate::{
+    board::{Board, Move},
+    fen::{ParseFenError, STARTING_POSITION},
+    game::{Game, GameOutcome},
+    san::ParseSanError,
+    Side
+};
+use std::{
+    fmt::{self, Write},
+    mem
+};
+
+/// PGN export format lines shouldn't be longer than this
+const MAX_LINE_LENGTH: usize = 79;
+/// The tags every PGN game has, in the order they're written
+const SEVEN_TAG_ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*")
+];
+/// The move suffix annotations and the NAGs they stand for
+const SUFFIX_ANNOTATIONS: [(&str, u8); 6] = [
+    ("!", 1),
+    ("?", 2),
+    ("!!", 3),
+    ("??", 4),
+    ("!?", 5),
+    ("?!", 6)
+];
+
+/// An error parsing games from a PGN file
+#[derive(Debug, Fail)]
+pub enum ParsePgnError {
+    #[fail(display = "line {}: unexpected {:?}", _0, _1)]
+    UnexpectedToken(usize, String),
+    #[fail(display = "line {}: unexpected end of file", _0)]
+    UnexpectedEof(usize),
+    #[fail(display = "line {}: invalid FEN tag: {}", _0, _1)]
+    InvalidFen(usize, #[cause] ParseFenError),
+    #[fail(display = "line {}: {}", _0, _1)]
+    InvalidMove(usize, #[cause] ParseSanError)
+}
+
+/// How a game ended, as far as PGN is concerned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    /// The game is still going on, was abandoned, or the result is unknown
+    Unknown
+}
+impl GameResult {
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "1-0" => Some(GameResult::WhiteWins),
+            "0-1" => Some(GameResult::BlackWins),
+            "1/2-1/2" => Some(GameResult::Draw),
+            "*" => Some(GameResult::Unknown),
+            _ => None
+        }
+    }
+}
+impl From<GameOutcome> for GameResult {
+    fn from(outcome: GameOutcome) -> Self {
+        match outcome.winner() {
+            Some(Side::White) => GameResult::WhiteWins,
+            Some(Side::Black) => GameResult::BlackWins,
+            None => GameResult::Draw
+        }
+    }
+}
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+            GameResult::Unknown => "*"
+        })
+    }
+}
+
+/// A move in a game tree, along with its annotations and the moves that
+/// could have been played instead
+#[derive(Clone, Debug, Default)]
+pub struct Node {
+    /// The move that was made, or `None` for a null move (`--`) that only
+    /// passes the turn
+    pub move_: Option<Move>,
+    /// Numeric Annotation Glyphs, like 1 for a good move (`!`)
+    pub nags: Vec<u8>,
+    /// A comment about the position before the move
+    pub comment_before: Option<String>,
+    /// A comment about the move
+    pub comment: Option<String>,
+    /// Alternative lines, each starting from the position before this move
+    pub variations: Vec<Vec<Node>>,
+    /// A comment that came after the variations
+    pub comment_after_variations: Option<String>
+}
+impl Node {
+    /// Create a node without any annotations
+    pub fn new(move_: Option<Move>) -> Self {
+        Self {
+            move_,
+            ..Self::default()
+        }
+    }
+}
+
+/// A game as stored in a PGN file
+#[derive(Clone, Debug)]
+pub struct PgnGame {
+    /// All tags in the order they appeared, including the seven tag roster
+    pub tags: Vec<(String, String)>,
+    /// The main line
+    pub moves: Vec<Node>,
+    pub result: GameResult
+}
+impl Default for PgnGame {
+    fn default() -> Self {
+        Self {
+            tags: Vec::new(),
+            moves: Vec::new(),
+            result: GameResult::Unknown
+        }
+    }
+}
+impl PgnGame {
+    /// Create a PGN game from the main line of a game, using its outcome as
+    /// the result
+    pub fn from_game(game: &Game) -> Self {
+        let mut start = game.clone();
+        while start.undo().is_some() {}
+
+        let mut pgn = Self::default();
+        if start.board().to_fen() != STARTING_POSITION {
+            pgn.set_tag("SetUp", "1");
+            pgn.set_tag("FEN", &start.board().to_fen());
+        }
+        pgn.moves = game.history().iter().map(|entry| Node::new(entry.move_)).collect();
+        if let Some(outcome) = game.outcome() {
+            pgn.result = outcome.into();
+        }
+        pgn
+    }
+
+    /// Return the value of a tag
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| &**value)
+    }
+    /// Set the value of a tag, replacing any previous value
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(key, _)| key == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string()))
+        }
+    }
+
+    /// Return the board the game starts from, which is set by the FEN tag
+    pub fn start(&self) -> Result<Board, ParseFenError> {
+        Board::from_fen(self.tag("FEN").unwrap_or(STARTING_POSITION))
+    }
+    /// Replay the main line of the game
+    pub fn game(&self) -> Result<Game, ParseFenError> {
+        let mut game = Game::new(self.start()?);
+        for node in &self.moves {
+            match node.move_ {
+                Some(m) => game.make_move(m),
+                None => game.pass_turn()
+            }
+        }
+        Ok(game)
+    }
+}
+
+/// Write the movetext of a line played from `board`, including variations
+fn write_line(out: &mut TokenWriter, board: &Board, line: &[Node]) {
+    let mut board = board.clone();
+    // Null moves don't count moves on the board, so count them here
+    let mut fullmove = board.fullmove_number();
+    let mut needs_number = true;
+    for node in line {
+        if let Some(comment) = &node.comment_before {
+            out.comment(comment);
+            needs_number = true;
+        }
+        let side = board.side_to_move();
+        match side {
+            Side::White => out.token(format!("{}.", fullmove)),
+            Side::Black if needs_number => out.token(format!("{}...", fullmove)),
+            Side::Black => ()
+        }
+        needs_number = false;
+        match node.move_ {
+            Some(m) => out.token(board.to_pgn_san(m)),
+            None => out.token(String::from("--"))
+        }
+        for nag in &node.nags {
+            out.token(format!("${}", nag));
+        }
+        if let Some(comment) = &node.comment {
+            out.comment(comment);
+            needs_number = true;
+        }
+        for variation in &node.variations {
+            out.open = true;
+            write_line(out, &board, variation);
+            out.close();
+            needs_number = true;
+        }
+        if let Some(comment) = &node.comment_after_variations {
+            out.comment(comment);
+            needs_number = true;
+        }
+        match node.move_ {
+            Some(m) => { board.move_(m); },
+            None => { board.pass_turn(); }
+        }
+        if side == Side::Black {
+            fullmove += 1;
+        }
+    }
+}
+/// Writes the game in PGN export format: the seven tag roster first, then
+/// any other tags, then the movetext wrapped at 79 characters
+impl fmt::Display for PgnGame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let escape = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"");
+        for &(name, default) in &SEVEN_TAG_ROSTER {
+            let value = match name {
+                "Result" => self.result.to_string(),
+                _ => self.tag(name).unwrap_or(default).to_string()
+            };
+            writeln!(f, "[{} \"{}\"]", name, escape(&value))?;
+        }
+        for (name, value) in &self.tags {
+            if SEVEN_TAG_ROSTER.iter().all(|&(roster, _)| roster != name) {
+                writeln!(f, "[{} \"{}\"]", name, escape(value))?;
+            }
+        }
+        writeln!(f)?;
+
+        let mut out = TokenWriter::default();
+        // An invalid FEN tag has already been written, nothing to replay
+        // the moves on though
+        if let Ok(board) = self.start() {
+            write_line(&mut out, &board, &self.moves);
+        }
+        out.token(self.result.to_string());
+        writeln!(f, "{}", out.finish())
+    }
+}
+
+/// Joins tokens with spaces, wrapping lines that would get too long
+#[derive(Default)]
+struct TokenWriter {
+    output: String,
+    line_length: usize,
+    /// Whether the next token starts a variation
+    open: bool
+}
+impl TokenWriter {
+    fn token(&mut self, mut token: String) {
+        if mem::replace(&mut self.open, false) {
+            token.insert(0, '(');
+        }
+        if self.line_length > 0 && self.line_length + 1 + token.len() > MAX_LINE_LENGTH {
+            self.output.push('\n');
+            self.line_length = 0;
+        } else if self.line_length > 0 {
+            self.output.push(' ');
+            self.line_length += 1;
+        }
+        self.line_length += token.len();
+        self.output.push_str(&token);
+    }
+    fn comment(&mut self, comment: &str) {
+        // Comments can't contain their own end, and may be wrapped anywhere
+        let comment = format!("{{{}}}", comment.replace('}', "").trim());
+        for word in comment.split_whitespace() {
+            self.token(word.to_string());
+        }
+    }
+    fn close(&mut self) {
+        if mem::replace(&mut self.open, false) {
+            self.token(String::from("("));
+        }
+        self.output.push(')');
+        self.line_length += 1;
+    }
+    fn finish(self) -> String {
+        self.output
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    OpenBracket,
+    CloseBracket,
+    OpenParen,
+    CloseParen,
+    String(String),
+    Comment(String),
+    Nag(u8),
+    /// Move suffix annotations like `!?`
+    Suffix(String),
+    Symbol(String),
+    Period
+}
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::OpenBracket => f.write_str("["),
+            Token::CloseBracket => f.write_str("]"),
+            Token::OpenParen => f.write_str("("),
+            Token::CloseParen => f.write_str(")"),
+            Token::String(string) => write!(f, "{:?}", string),
+            Token::Comment(comment) => write!(f, "{{{}}}", comment),
+            Token::Nag(nag) => write!(f, "${}", nag),
+            Token::Suffix(s) | Token::Symbol(s) => f.write_str(s),
+            Token::Period => f.write_str(".")
+        }
+    }
+}
+
+struct Tokenizer<'a> {
+    input: &'a str,
+    pos: usize,
+    line: usize
+}
+impl<'a> Tokenizer<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, pos: 0, line: 1 }
+    }
+    fn peek_char(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek_char()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+    /// Consume characters as long as `f` returns true
+    fn take_while<F: Fn(char) -> bool>(&mut self, f: F) -> &'a str {
+        let start = self.pos;
+        while self.peek_char().map(&f).unwrap_or(false) {
+            self.next_char();
+        }
+        &self.input[start..self.pos]
+    }
+    fn next_token(&mut self) -> Result<Option<Token>, ParsePgnError> {
+        loop {
+            let at_line_start = self.pos == 0 || self.input[..self.pos].ends_with('\n');
+            let c = match self.next_char() {
+                Some(c) => c,
+                None => return Ok(None)
+            };
+            let token = match c {
+                // Escaped lines and rest of line comments are ignored
+                '%' if at_line_start => {
+                    self.take_while(|c| c != '\n');
+                    continue;
+                },
+                ';' => {
+                    self.take_while(|c| c != '\n');
+                    continue;
+                },
+                c if c.is_whitespace() => continue,
+                '[' => Token::OpenBracket,
+                ']' => Token::CloseBracket,
+                '(' => Token::OpenParen,
+                ')' => Token::CloseParen,
+                '.' => Token::Period,
+                '"' => {
+                    let mut string = String::new();
+                    loop {
+                        match self.next_char() {
+                            Some('"') => break,
+                            Some('\\') => match self.next_char() {
+                                Some(c) => string.push(c),
+                                None => return Err(ParsePgnError::UnexpectedEof(self.line))
+                            },
+                            Some(c) => string.push(c),
+                            None => return Err(ParsePgnError::UnexpectedEof(self.line))
+                        }
+                    }
+                    Token::String(string)
+                },
+                '{' => {
+                    let comment = self.take_while(|c| c != '}');
+                    if self.next_char().is_none() {
+                        return Err(ParsePgnError::UnexpectedEof(self.line));
+                    }
+                    Token::Comment(comment.split_whitespace().collect::<Vec<_>>().join(" "))
+                },
+                '$' => {
+                    let number = self.take_while(|c| c.is_ascii_digit());
+                    match number.parse() {
+                        Ok(nag) => Token::Nag(nag),
+                        Err(_) => return Err(ParsePgnError::UnexpectedToken(self.line, format!("${}", number)))
+                    }
+                },
+                '!' | '?' => {
+                    let start = self.pos - 1;
+                    self.take_while(|c| c == '!' || c == '?');
+                    Token::Suffix(self.input[start..self.pos].to_string())
+                },
+                '*' => Token::Symbol(String::from("*")),
+                c if c.is_ascii_alphanumeric() || c == '-' => {
+                    let start = self.pos - 1;
+                    self.take_while(|c| c.is_ascii_alphanumeric() || "_+#=:-/".contains(c));
+                    Token::Symbol(self.input[start..self.pos].to_string())
+                },
+                c => return Err(ParsePgnError::UnexpectedToken(self.line, c.to_string()))
+            };
+            return Ok(Some(token));
+        }
+    }
+}
+
+/// A line that's being parsed, along with the position it's at
+struct LineState {
+    nodes: Vec<Node>,
+    board: Board,
+    /// The position before the last move, where variations start from
+    before: Option<Board>,
+    /// A comment to attach to the next move
+    comment: Option<String>
+}
+impl LineState {
+    fn new(board: Board) -> Self {
+        Self {
+            nodes: Vec::new(),
+            board,
+            before: None,
+            comment: None
+        }
+    }
+}
+
+fn join_comment(old: Option<String>, comment: String) -> Option<String> {
+    match old {
+        Some(old) => Some(format!("{} {}", old, comment)),
+        None => Some(comment)
+    }
+}
+
+/// Parse all games in a PGN file. Moves are checked to be legal, so every
+/// game can be replayed using `PgnGame::game`.
+pub fn parse(input: &str) -> Result<Vec<PgnGame>, ParsePgnError> {
+    let mut tokens = Tokenizer::new(input);
+    let mut games = Vec::new();
+    let mut peeked = None;
+
+    macro_rules! next {
+        () => {
+            match peeked.take() {
+                Some(token) => Some(token),
+                None => tokens.next_token()?
+            }
+        };
+    }
+    macro_rules! unexpected {
+        ($token:expr) => {
+            return Err(match $token {
+                Some(token) => ParsePgnError::UnexpectedToken(tokens.line, token.to_string()),
+                None => ParsePgnError::UnexpectedEof(tokens.line)
+            })
+        };
+    }
+
+    loop {
+        let mut game = PgnGame::default();
+
+        // Tag pairs
+        loop {
+            match next!() {
+                Some(Token::OpenBracket) => (),
+                token => {
+                    peeked = token;
+                    break;
+                }
+            }
+            let name = match next!() {
+                Some(Token::Symbol(name)) => name,
+                token => unexpected!(token)
+            };
+            let value = match next!() {
+                Some(Token::String(value)) => value,
+                token => unexpected!(token)
+            };
+            match next!() {
+                Some(Token::CloseBracket) => (),
+                token => unexpected!(token)
+            }
+            game.tags.push((name, value));
+        }
+        if game.tags.is_empty() && peeked.is_none() {
+            break;
+        }
+        // The result at the end of the movetext wins, but it may be missing
+        if let Some(result) = game.tag("Result").and_then(GameResult::from_str) {
+            game.result = result;
+        }
+
+        let start = game.start().map_err(|err| ParsePgnError::InvalidFen(tokens.line, err))?;
+        let mut stack: Vec<LineState> = Vec::new();
+        let mut line = LineState::new(start);
+
+        // Movetext
+        loop {
+            let token = match next!() {
+                Some(token) => token,
+                None if stack.is_empty() => break,
+                None => return Err(ParsePgnError::UnexpectedEof(tokens.line))
+            };
+            match token {
+                Token::OpenBracket if stack.is_empty() => {
+                    // The result is missing, this is the next game already
+                    peeked = Some(token);
+                    break;
+                },
+                Token::Period => (),
+                Token::Symbol(ref s) if s.chars().all(|c| c.is_ascii_digit()) => (),
+                Token::Symbol(ref s) if stack.is_empty() && GameResult::from_str(s).is_some() => {
+                    game.result = GameResult::from_str(s).unwrap();
+                    break;
+                },
+                Token::Symbol(s) => {
+                    let m = if s == "--" {
+                        None
+                    } else {
+                        let m = line.board.parse_san(&s).map_err(|err| ParsePgnError::InvalidMove(tokens.line, err))?;
+                        Some(m)
+                    };
+                    let mut node = Node::new(m);
+                    node.comment_before = line.comment.take();
+                    line.before = Some(line.board.clone());
+                    match m {
+                        Some(m) => { line.board.move_(m); },
+                        None => { line.board.pass_turn(); }
+                    }
+                    line.nodes.push(node);
+                },
+                Token::Nag(nag) => match line.nodes.last_mut() {
+                    Some(node) => node.nags.push(nag),
+                    None => unexpected!(Some(token))
+                },
+                Token::Suffix(ref suffix) => {
+                    let nag = SUFFIX_ANNOTATIONS.iter().find(|&&(s, _)| s == suffix);
+                    match (line.nodes.last_mut(), nag) {
+                        (Some(node), Some(&(_, nag))) => node.nags.push(nag),
+                        _ => unexpected!(Some(token))
+                    }
+                },
+                Token::Comment(comment) => match line.nodes.last_mut() {
+                    // Keep comments on the same side of the variations
+                    Some(node) if line.comment.is_none() && !node.variations.is_empty() => {
+                        node.comment_after_variations = join_comment(node.comment_after_variations.take(), comment)
+                    },
+                    Some(node) if line.comment.is_none() => node.comment = join_comment(node.comment.take(), comment),
+                    _ => line.comment = join_comment(line.comment.take(), comment)
+                },
+                Token::OpenParen => {
+                    let before = match &line.before {
+                        Some(before) => before.clone(),
+                        None => unexpected!(Some(token))
+                    };
+                    stack.push(mem::replace(&mut line, LineState::new(before)));
+                },
+                Token::CloseParen => {
+                    let mut variation = match stack.pop() {
+                        Some(parent) => mem::replace(&mut line, parent),
+                        None => unexpected!(Some(token))
+                    };
+                    // A comment after everything else belongs to the last
+                    // move, so there must be one
+                    match (variation.comment.take(), variation.nodes.last_mut()) {
+                        (Some(comment), Some(node)) => node.comment = join_comment(node.comment.take(), comment),
+                        (_, None) => unexpected!(Some(token)),
+                        (None, Some(_)) => ()
+                    }
+                    match line.nodes.last_mut() {
+                        Some(node) => node.variations.push(variation.nodes),
+                        None => unexpected!(Some(token))
+                    }
+                },
+                token => unexpected!(Some(token))
+            }
+        }
+        // A comment after everything else belongs to the last move
+        if let (Some(comment), Some(node)) = (line.comment, line.nodes.last_mut()) {
+            node.comment = join_comment(node.comment.take(), comment);
+        }
+        game.moves = line.nodes;
+        games.push(game);
+    }
+    Ok(games)
+}
+
+/// Write several games in PGN export format, separated by empty lines
+pub fn write<W: Write>(out: &mut W, games: &[PgnGame]) -> fmt::Result {
+    for (i, game) in games.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        write!(out, "{}", game)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_one(input: &str) -> PgnGame {
+        let mut games = parse(input).unwrap();
+        assert_eq!(games.len(), 1);
+        games.remove(0)
+    }
+    fn san(board: &Board, nodes: &[Node]) -> Vec<String> {
+        let moves: Vec<Move> = nodes.iter().map(|node| node.move_.unwrap()).collect();
+        board.line_to_san(&moves)
+    }
+
+    #[test]
+    fn tags() {
+        let game = parse_one(r#"
+            [Event "Casual \"blitz\""]
+            [White "Alice"]
+            [Annotator "C:\\Users"]
+
+            1. e4 *
+        "#);
+        assert_eq!(game.tag("Event"), Some("Casual \"blitz\""));
+        assert_eq!(game.tag("White"), Some("Alice"));
+        assert_eq!(game.tag("Annotator"), Some("C:\\Users"));
+        assert_eq!(game.tag("Black"), None);
+
+        let output = game.to_string();
+        assert!(output.starts_with("[Event \"Casual \\\"blitz\\\"\"]\n[Site \"?\"]\n"));
+        assert!(output.contains("[Annotator \"C:\\\\Users\"]\n"));
+    }
+    #[test]
+    fn fen_tag() {
+        let game = parse_one(r#"
+            [SetUp "1"]
+            [FEN "4k3/8/8/8/8/8/4P3/4K3 b - - 0 12"]
+
+            12... Kd7 13. e4 *
+        "#);
+        let board = game.start().unwrap();
+        assert_eq!(san(&board, &game.moves), ["Kd7", "e4"]);
+        assert!(game.to_string().contains("\n12... Kd7 13. e4 *\n"));
+    }
+    #[test]
+    fn comments() {
+        let game = parse_one("{Opening} 1. e4 {best by test} e5 ; rest of line\n2. Nf3 {a} {b} *");
+        assert_eq!(game.moves[0].comment_before.as_deref(), Some("Opening"));
+        assert_eq!(game.moves[0].comment.as_deref(), Some("best by test"));
+        assert_eq!(game.moves[1].comment, None);
+        assert_eq!(game.moves[2].comment.as_deref(), Some("a b"));
+        assert!(game.to_string().contains("{Opening} 1. e4 {best by test} 1... e5 2. Nf3 {a b} *"));
+    }
+    #[test]
+    fn nags() {
+        let game = parse_one("1. e4! e5?! 2. Qh5 $2 $13 *");
+        assert_eq!(game.moves[0].nags, [1]);
+        assert_eq!(game.moves[1].nags, [6]);
+        assert_eq!(game.moves[2].nags, [2, 13]);
+        assert!(game.to_string().contains("1. e4 $1 e5 $6 2. Qh5 $2 $13 *"));
+
+        assert!(parse("$1 1. e4 *").is_err());
+    }
+    #[test]
+    fn nested_variations() {
+        let game = parse_one("1. e4 (1. d4 d5 (1... Nf6 2. c4) 2. c4) (1. c4) e5 (1... c5 {Sicilian}) *");
+        let board = Board::default();
+        assert_eq!(san(&board, &game.moves), ["e4", "e5"]);
+
+        let e4 = &game.moves[0];
+        assert_eq!(e4.variations.len(), 2);
+        assert_eq!(san(&board, &e4.variations[0]), ["d4", "d5", "c4"]);
+        assert_eq!(san(&board, &e4.variations[1]), ["c4"]);
+
+        let mut after_d4 = board.clone();
+        after_d4.move_(e4.variations[0][0].move_.unwrap());
+        let d5 = &e4.variations[0][1];
+        assert_eq!(d5.variations.len(), 1);
+        assert_eq!(san(&after_d4, &d5.variations[0]), ["Nf6", "c4"]);
+
+        let mut after_e4 = board.clone();
+        after_e4.move_(e4.move_.unwrap());
+        let e5 = &game.moves[1];
+        assert_eq!(san(&after_e4, &e5.variations[0]), ["c5"]);
+        assert_eq!(e5.variations[0][0].comment.as_deref(), Some("Sicilian"));
+
+        assert!(game.to_string().contains("1. e4 (1. d4 d5 (1... Nf6 2. c4) 2. c4) (1. c4) 1... e5 (1... c5 {Sicilian}) *"));
+    }
+    #[test]
+    fn comment_after_variation() {
+        let game = parse_one("1. e4 (1. d4) {comment} e5 *");
+        assert_eq!(game.moves[0].comment, None);
+        assert_eq!(game.moves[0].comment_after_variations.as_deref(), Some("comment"));
+
+        let written = game.to_string();
+        assert!(written.contains("1. e4 (1. d4) {comment} 1... e5 *"));
+        assert_eq!(parse_one(&written).to_string(), written);
+
+        let game = parse_one("1. e4 {before} (1. d4) {after} *");
+        assert!(game.to_string().contains("1. e4 {before} (1. d4) {after} *"));
+    }
+    #[test]
+    fn invalid_variations() {
+        // No move for the variation to replace
+        assert!(parse("(1. d4) 1. e4 *").is_err());
+        assert!(parse("1. e4 () e5 *").is_err());
+        assert!(parse("1. e4 (1. d4 *").is_err());
+        assert!(parse("1. e4 ) *").is_err());
+        assert!(parse("1. e4 (1. e5) *").is_err());
+    }
+    #[test]
+    fn multiple_games() {
+        let games = parse(r#"
+            [Event "First"]
+            1. e4 e5 1-0
+
+            [Event "Second"]
+            1. d4 0-1
+            [Event "Third"]
+            1. c4
+        "#).unwrap();
+        assert_eq!(games.len(), 3);
+        let events: Vec<_> = games.iter().map(|game| game.tag("Event").unwrap()).collect();
+        assert_eq!(events, ["First", "Second", "Third"]);
+        let moves: Vec<_> = games.iter().map(|game| game.moves.len()).collect();
+        assert_eq!(moves, [2, 1, 1]);
+
+        let mut output = String::new();
+        write(&mut output, &games).unwrap();
+        let again = parse(&output).unwrap();
+        assert_eq!(again.len(), 3);
+        assert!(parse("").unwrap().is_empty());
+    }
+    #[test]
+    fn result() {
+        // The movetext result is what counts
+        let game = parse_one("[Result \"1-0\"]\n1. f3 e5 2. g4 Qh4# 0-1");
+        assert_eq!(game.result, GameResult::BlackWins);
+        assert!(game.to_string().contains("[Result \"0-1\"]"));
+        assert_eq!(game.game().unwrap().outcome(), Some(GameOutcome::Checkmate { winner: Side::Black }));
+
+        // But the tag is used when there is none
+        let game = parse_one("[Result \"1/2-1/2\"]\n1. e4 e5");
+        assert_eq!(game.result, GameResult::Draw);
+        let game = parse_one("1. e4 e5");
+        assert_eq!(game.result, GameResult::Unknown);
+    }
+    #[test]
+    fn round_trip() {
+        let input = r#"
+            [Event "Round trip"]
+            [Site "Somewhere"]
+            [Date "2024.01.02"]
+            [Round "1"]
+            [White "A"]
+            [Black "B"]
+            [Result "1/2-1/2"]
+            [Opening "Ruy Lopez"]
+
+            {Start} 1. e4 e5 2. Nf3 {develops} Nc6 (2... d6 $6 3. d4 (3. Bc4 Be7) exd4)
+            3. Bb5 a6 4. Ba4 Nf6 5. O-O Be7 6. Re1 b5 7. Bb3 d6 8. c3 O-O 9. h3 Nb8
+            10. d4 Nbd7 11. Nbd2 Bb7 12. Bc2 Re8 13. Nf1 Bf8 14. Ng3 g6 15. a4 c5
+            16. d5 c4 17. Bg5 h6 18. Be3 Nc5 19. Qd2 h5 20. Bg5 Be7 -- 1/2-1/2
+        "#;
+        let game = parse_one(input);
+        let written = game.to_string();
+        assert!(written.lines().all(|line| line.len() <= MAX_LINE_LENGTH));
+
+        let again = parse_one(&written);
+        assert_eq!(again.to_string(), written);
+        assert_eq!(again.tags, game.tags);
+        assert_eq!(again.moves.len(), 41);
+        assert_eq!(again.moves[40].move_, None);
+        assert_eq!(
+            again.game().unwrap().board().to_fen(),
+            game.game().unwrap().board().to_fen()
+        );
+    }
+    #[test]
+    fn from_game() {
+        let mut game = Game::new(Board::default());
+        for san in &["f3", "e5", "g4", "Qh4#"] {
+            let m = game.board().parse_san(san).unwrap();
+            game.make_move(m);
+        }
+        let pgn = PgnGame::from_game(&game);
+        assert_eq!(pgn.result, GameResult::BlackWins);
+        assert_eq!(pgn.tag("FEN"), None);
+        assert!(pgn.to_string().ends_with("\n1. f3 e5 2. g4 Qh4# 0-1\n"));
+    }
+}
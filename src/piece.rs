@@ -6,7 +6,7 @@ use crate::{
 use std::fmt;
 
 /// A chess piece on the board, a kind of piece and what side it is on
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Piece {
     pub kind: PieceKind,
     pub side: Side
@@ -46,6 +46,23 @@ impl Piece {
         }
     }
 
+    /// Get the ASCII letter used for this piece in FEN strings: uppercase for
+    /// white and lowercase for black
+    pub fn to_ascii(&self) -> char {
+        match self.side {
+            Side::Black => self.kind.letter().to_ascii_lowercase(),
+            Side::White => self.kind.letter()
+        }
+    }
+    /// Parse a piece from its FEN letter, see `to_ascii`
+    pub fn from_ascii(c: char) -> Option<Self> {
+        let kind = PieceKind::from_letter(c.to_ascii_uppercase())?;
+        Some(Self {
+            kind,
+            side: if c.is_ascii_uppercase() { Side::White } else { Side::Black }
+        })
+    }
+
     /// All moves this piece can make.
     /// Note: Some moves may or may not be possible, depending on the position on the board.
     pub fn moves(&self) -> (ArrayVec<[Pos; 10]>, bool) {
@@ -116,7 +133,7 @@ impl fmt::Display for Piece {
     }
 }
 /// A kind of chess piece
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum PieceKind {
     Pawn,
     Knight,
@@ -141,4 +158,28 @@ impl PieceKind {
             PieceKind::King => 0
         }
     }
+    /// Return the uppercase English letter for this kind of piece, as used in
+    /// FEN and algebraic notation
+    pub fn letter(self) -> char {
+        match self {
+            PieceKind::Pawn => 'P',
+            PieceKind::Knight => 'N',
+            PieceKind::Bishop => 'B',
+            PieceKind::Rook => 'R',
+            PieceKind::Queen => 'Q',
+            PieceKind::King => 'K'
+        }
+    }
+    /// Parse an uppercase English letter into a kind of piece, see `letter`
+    pub fn from_letter(c: char) -> Option<Self> {
+        match c {
+            'P' => Some(PieceKind::Pawn),
+            'N' => Some(PieceKind::Knight),
+            'B' => Some(PieceKind::Bishop),
+            'R' => Some(PieceKind::Rook),
+            'Q' => Some(PieceKind::Queen),
+            'K' => Some(PieceKind::King),
+            _ => None
+        }
+    }
 }
@@ -0,0 +1,363 @@
+use crate::{
+    board::{self, Board, Move},
+    fen::write_pos,
+    piece::PieceKind,
+    Pos
+};
+use std::fmt::Write;
+
+/// An error parsing a move from a string
+#[derive(Debug, Fail)]
+pub enum ParseSanError {
+    #[fail(display = "invalid move notation {:?}", _0)]
+    InvalidFormat(String),
+    #[fail(display = "illegal move {:?}", _0)]
+    IllegalMove(String),
+    #[fail(display = "ambiguous move {:?}, specify which piece to move", _0)]
+    AmbiguousMove(String)
+}
+
+fn parse_file(c: u8) -> Option<i8> {
+    match c {
+        b'a'..=b'h' => Some((c - b'a') as i8),
+        _ => None
+    }
+}
+fn parse_rank(c: u8) -> Option<i8> {
+    match c {
+        b'1'..=b'8' => Some(board::WIDTH - (c - b'0') as i8),
+        _ => None
+    }
+}
+fn parse_promotion(c: u8) -> Option<PieceKind> {
+    match PieceKind::from_letter((c as char).to_ascii_uppercase()) {
+        Some(PieceKind::Pawn) | Some(PieceKind::King) | None => None,
+        kind => kind
+    }
+}
+/// Strip check and mate markers, annotations like `!?` and the `e.p.` suffix
+fn strip_suffixes(s: &str) -> &str {
+    let s = s.trim();
+    let s = s.strip_suffix("e.p.").unwrap_or(s).trim_end();
+    s.trim_end_matches(['+', '#', '!', '?'])
+}
+
+impl Board {
+    /// Format a legal move in Standard Algebraic Notation, like `Nbd7`,
+    /// `O-O-O`, `e8=N+` or `Qxf7#`. En passant captures are marked with a
+    /// trailing `e.p.`, see `to_pgn_san` for the form used by PGN.
+    pub fn to_san(&self, m: Move) -> String {
+        let mut san = self.to_pgn_san(m);
+        if m.flags.contains(board::MoveFlags::EN_PASSANT) {
+            san.push_str(" e.p.");
+        }
+        san
+    }
+    /// Format a legal move in Standard Algebraic Notation exactly like the
+    /// PGN standard wants it, without marking en passant captures
+    pub fn to_pgn_san(&self, m: Move) -> String {
+        let mut san = String::new();
+        let kind = self.get(m.from).map(|piece| piece.kind).unwrap_or(PieceKind::Pawn);
+
+        if m.is_castling() {
+            san.push_str(if m.to.0 > m.from.0 { "O-O" } else { "O-O-O" });
+        } else if kind == PieceKind::Pawn {
+            if m.is_capture() {
+                san.push((b'a' + m.from.0 as u8) as char);
+                san.push('x');
+            }
+            write_pos(&mut san, m.to).unwrap();
+            if let Some(promotion) = m.promotion {
+                san.push('=');
+                san.push(promotion.letter());
+            }
+        } else {
+            san.push(kind.letter());
+
+            // Other pieces of the same kind that could also go there
+            let others: Vec<Pos> = self.legal_moves(self.side_to_move())
+                .into_iter()
+                .filter(|other| other.to == m.to && other.from != m.from)
+                .filter(|other| self.get(other.from).map(|piece| piece.kind) == Some(kind))
+                .map(|other| other.from)
+                .collect();
+            if !others.is_empty() {
+                let Pos(x, y) = m.from;
+                let file = (b'a' + x as u8) as char;
+                let rank = board::WIDTH - y;
+                if others.iter().all(|other| other.0 != x) {
+                    san.push(file);
+                } else if others.iter().all(|other| other.1 != y) {
+                    write!(san, "{}", rank).unwrap();
+                } else {
+                    write!(san, "{}{}", file, rank).unwrap();
+                }
+            }
+
+            if m.is_capture() {
+                san.push('x');
+            }
+            write_pos(&mut san, m.to).unwrap();
+        }
+
+        let mut after = self.clone();
+        after.move_(m);
+        let side = after.side_to_move();
+        if after.check(side).is_some() {
+            san.push(if after.legal_moves(side).is_empty() { '#' } else { '+' });
+        }
+        san
+    }
+    /// Format a line of moves starting from this board in Standard Algebraic
+    /// Notation. Every move must be legal after the ones before it.
+    pub fn line_to_san(&self, moves: &[Move]) -> Vec<String> {
+        let mut board = self.clone();
+        moves.iter()
+            .map(|&m| {
+                let san = board.to_san(m);
+                board.move_(m);
+                san
+            })
+            .collect()
+    }
+
+    /// Parse a move in Standard Algebraic Notation, like `Nbd7` or `exd6`,
+    /// into a legal move for the side to move. Check markers and annotations
+    /// like `!?` are ignored.
+    pub fn parse_san(&self, s: &str) -> Result<Move, ParseSanError> {
+        let invalid = || ParseSanError::InvalidFormat(s.to_string());
+        let san = strip_suffixes(s);
+        if !san.is_ascii() {
+            return Err(invalid());
+        }
+        let legal = self.legal_moves(self.side_to_move());
+
+        let castling = match san {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            _ => None
+        };
+        if let Some(kingside) = castling {
+            return legal.into_iter()
+                .find(|m| m.is_castling() && (m.to.0 > m.from.0) == kingside)
+                .ok_or_else(|| ParseSanError::IllegalMove(s.to_string()));
+        }
+
+        let mut bytes = san.as_bytes();
+        let kind = match bytes.first() {
+            Some(&c) if c.is_ascii_uppercase() => {
+                bytes = &bytes[1..];
+                PieceKind::from_letter(c as char).ok_or_else(invalid)?
+            },
+            _ => PieceKind::Pawn
+        };
+
+        let mut promotion = None;
+        if let Some((&last, rest)) = bytes.split_last() {
+            if kind == PieceKind::Pawn && last.is_ascii_alphabetic() && !(b'a'..=b'h').contains(&last) {
+                promotion = Some(parse_promotion(last).ok_or_else(invalid)?);
+                bytes = match rest.split_last() {
+                    Some((b'=', rest)) => rest,
+                    _ => rest
+                };
+            }
+        }
+
+        if bytes.len() < 2 {
+            return Err(invalid());
+        }
+        let (rest, to) = bytes.split_at(bytes.len() - 2);
+        let to = Pos(
+            parse_file(to[0]).ok_or_else(invalid)?,
+            parse_rank(to[1]).ok_or_else(invalid)?
+        );
+        let (rest, capture) = match rest.split_last() {
+            Some((b'x', rest)) | Some((b':', rest)) => (rest, true),
+            _ => (rest, false)
+        };
+        let (file, rank) = match *rest {
+            [] => (None, None),
+            [c] => match (parse_file(c), parse_rank(c)) {
+                (None, None) => return Err(invalid()),
+                disambiguation => disambiguation
+            },
+            [file, rank] => (
+                Some(parse_file(file).ok_or_else(invalid)?),
+                Some(parse_rank(rank).ok_or_else(invalid)?)
+            ),
+            _ => return Err(invalid())
+        };
+
+        let mut candidates = legal.into_iter().filter(|m| {
+            m.to == to
+                && m.promotion == promotion
+                && (!capture || m.is_capture())
+                && file.map(|x| m.from.0 == x).unwrap_or(true)
+                && rank.map(|y| m.from.1 == y).unwrap_or(true)
+                && self.get(m.from).map(|piece| piece.kind) == Some(kind)
+        });
+        match (candidates.next(), candidates.next()) {
+            (Some(m), None) => Ok(m),
+            (Some(_), Some(_)) => Err(ParseSanError::AmbiguousMove(s.to_string())),
+            (None, _) => Err(ParseSanError::IllegalMove(s.to_string()))
+        }
+    }
+    /// Parse a move in long algebraic notation, like `e2e4`, `e2-e4`,
+    /// `e7e8q` or `e7xd8=Q`, as used by UCI, into a legal move for the side
+    /// to move
+    pub fn parse_long_algebraic(&self, s: &str) -> Result<Move, ParseSanError> {
+        let invalid = || ParseSanError::InvalidFormat(s.to_string());
+        let bytes = strip_suffixes(s).as_bytes();
+        if bytes.len() < 4 {
+            return Err(invalid());
+        }
+        let from = Pos(
+            parse_file(bytes[0]).ok_or_else(invalid)?,
+            parse_rank(bytes[1]).ok_or_else(invalid)?
+        );
+        let rest = match bytes[2] {
+            b'-' | b'x' | b':' => &bytes[3..],
+            _ => &bytes[2..]
+        };
+        if rest.len() < 2 {
+            return Err(invalid());
+        }
+        let to = Pos(
+            parse_file(rest[0]).ok_or_else(invalid)?,
+            parse_rank(rest[1]).ok_or_else(invalid)?
+        );
+        let promotion = match &rest[2..] {
+            [] => None,
+            [c] | [b'=', c] => Some(parse_promotion(*c).ok_or_else(invalid)?),
+            _ => return Err(invalid())
+        };
+        self.legal_moves_from(from)
+            .into_iter()
+            .filter(|m| self.get(m.from).map(|piece| piece.side) == Some(self.side_to_move()))
+            .find(|m| m.to == to && m.promotion == promotion)
+            .ok_or_else(|| ParseSanError::IllegalMove(s.to_string()))
+    }
+    /// Parse a move in either long algebraic notation or Standard Algebraic
+    /// Notation, see `parse_long_algebraic` and `parse_san`
+    pub fn parse_move(&self, s: &str) -> Result<Move, ParseSanError> {
+        match self.parse_long_algebraic(s) {
+            Err(ParseSanError::InvalidFormat(_)) => self.parse_san(s),
+            result => result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        Board::from_fen(fen).unwrap()
+    }
+    /// Parse a move, check that it's written back the same way, and return
+    /// the board after it
+    fn play(board: &Board, san: &str) -> Board {
+        let m = board.parse_san(san).unwrap();
+        assert_eq!(board.to_san(m), san);
+        let mut after = board.clone();
+        after.move_(m);
+        after
+    }
+
+    #[test]
+    fn disambiguation() {
+        // By file
+        let knights = board("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1");
+        play(&knights, "Nbd2");
+        play(&knights, "Nfd2");
+        play(&knights, "Nc3");
+
+        // By rank
+        let rooks = board("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1");
+        play(&rooks, "R1a3");
+        play(&rooks, "R5a3");
+        play(&rooks, "Rb5");
+
+        // Neither is enough for one of three queens
+        let queens = board("1K6/8/k7/8/4Q2Q/8/8/7Q w - - 0 1");
+        play(&queens, "Qh4e1");
+        play(&queens, "Qee1");
+        play(&queens, "Q1e1");
+
+        // A pinned knight can't go there, so no need to tell them apart
+        let pinned = board("4k3/4r3/8/8/8/1N6/4N3/4K3 w - - 0 1");
+        play(&pinned, "Nd4");
+        play(&pinned, "Nc1");
+        assert_eq!(pinned.parse_san("Nbd4").unwrap().from, Pos(1, 5));
+    }
+    #[test]
+    fn en_passant() {
+        let board = board("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3");
+        let m = board.parse_san("dxe3").unwrap();
+        assert!(m.flags.contains(board::MoveFlags::EN_PASSANT));
+        assert_eq!(board.to_san(m), "dxe3 e.p.");
+        assert_eq!(board.to_pgn_san(m), "dxe3");
+        assert_eq!(board.parse_san("dxe3 e.p.").unwrap(), m);
+        assert_eq!(board.parse_long_algebraic("d4e3").unwrap(), m);
+    }
+    #[test]
+    fn promotion() {
+        let board = board("3r4/4Pk2/8/8/8/8/8/4K3 w - - 0 1");
+        play(&board, "e8=Q+");
+        play(&board, "e8=N");
+        play(&board, "exd8=N+");
+        play(&board, "exd8=R");
+        assert_eq!(board.parse_san("e8Q").unwrap().promotion, Some(PieceKind::Queen));
+        assert_eq!(board.parse_long_algebraic("e7d8n").unwrap().promotion, Some(PieceKind::Knight));
+        assert!(board.parse_san("e8=K").is_err());
+        // Promoting is not optional
+        assert!(board.parse_san("e8").is_err());
+    }
+    #[test]
+    fn castling() {
+        let board = board("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let after = play(&board, "O-O");
+        play(&after, "O-O-O");
+        play(&board, "O-O-O");
+        assert_eq!(board.parse_san("0-0").unwrap(), board.parse_san("O-O").unwrap());
+        assert!(board.parse_san("0-0-0").unwrap().is_castling());
+
+        let no_rights = self::board("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1");
+        assert!(matches!(no_rights.parse_san("O-O"), Err(ParseSanError::IllegalMove(_))));
+    }
+    #[test]
+    fn check_and_mate() {
+        let board = Board::default();
+        let board = play(&board, "e4");
+        let board = play(&board, "d6");
+        play(&board, "Bb5+");
+
+        let board = Board::default();
+        let board = play(&board, "f3");
+        let board = play(&board, "e5");
+        let board = play(&board, "g4");
+        let mate = play(&board, "Qh4#");
+        assert!(mate.is_checkmate(mate.side_to_move()));
+
+        // Markers and annotations are ignored when parsing
+        assert_eq!(board.parse_san("Qh4").unwrap(), board.parse_san("Qh4#!?").unwrap());
+        assert_eq!(board.line_to_san(&[board.parse_san("Qh4").unwrap()]), ["Qh4#"]);
+    }
+    #[test]
+    fn rejected() {
+        let knights = board("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1");
+        assert!(matches!(knights.parse_san("Nd2"), Err(ParseSanError::AmbiguousMove(_))));
+        assert!(matches!(knights.parse_san("Ncd2"), Err(ParseSanError::IllegalMove(_))));
+
+        let pinned = board("4k3/4r3/8/8/8/1N6/4N3/4K3 w - - 0 1");
+        assert!(matches!(pinned.parse_san("Ned4"), Err(ParseSanError::IllegalMove(_))));
+
+        let start = Board::default();
+        assert!(matches!(start.parse_san("e5"), Err(ParseSanError::IllegalMove(_))));
+        assert!(matches!(start.parse_san("Nd4"), Err(ParseSanError::IllegalMove(_))));
+        assert!(matches!(start.parse_san("exd3"), Err(ParseSanError::IllegalMove(_))));
+        for invalid in &["", "Z", "Zf3", "Ni9", "e", "Nbcd1f3", "é4"] {
+            assert!(matches!(start.parse_san(invalid), Err(ParseSanError::InvalidFormat(_))), "{:?}", invalid);
+        }
+    }
+}
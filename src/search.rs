@@ -0,0 +1,243 @@
+use crate::{
+    board::Board,
+    eval::{Classic, Evaluator},
+    minimax::{Limits, MinimaxResult, SearchStats, MATE},
+    ordering::MoveOrdering,
+    transposition::TranspositionTable
+};
+use std::{
+    sync::atomic::AtomicBool,
+    time::{Duration, Instant}
+};
+
+/// The deepest iteration a search will ever start
+pub const MAX_DEPTH: u8 = 64;
+
+/// When to stop searching. A search stops at the first limit that is
+/// reached, or at `MAX_DEPTH` if there are none.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SearchLimits {
+    /// The maximum depth to search. The first iteration is always searched,
+    /// so 0 means the same as 1.
+    pub depth: Option<u8>,
+    /// The maximum number of positions to visit, including quiescence nodes
+    pub nodes: Option<u64>,
+    /// How long to search, counted from the start of the search
+    pub move_time: Option<Duration>,
+    /// Don't start another iteration after searching this long. Unlike
+    /// `move_time`, this never interrupts an iteration.
+    pub soft_time: Option<Duration>,
+    /// When to stop searching at the latest
+    pub deadline: Option<Instant>,
+    /// Ignore all other limits and keep searching until stopped through the
+    /// exit flag
+    pub infinite: bool
+}
+impl SearchLimits {
+    /// Search to the specified depth
+    pub fn depth(depth: u8) -> Self {
+        Self {
+            depth: Some(depth),
+            ..Self::default()
+        }
+    }
+    /// Search for the specified amount of time
+    pub fn move_time(time: Duration) -> Self {
+        Self {
+            move_time: Some(time),
+            ..Self::default()
+        }
+    }
+    /// Search until stopped through the exit flag
+    pub fn infinite() -> Self {
+        Self {
+            infinite: true,
+            ..Self::default()
+        }
+    }
+}
+
+/// Progress reported after each completed iteration of a search
+pub struct Iteration<'a> {
+    /// The result of this iteration
+    pub result: &'a MinimaxResult,
+    /// Statistics of all iterations so far
+    pub stats: SearchStats,
+    /// The time since the search started
+    pub elapsed: Duration
+}
+
+/// An iterative deepening search: searches to depth 1, then 2, and so on until
+/// a limit is reached, reusing the transposition table between iterations
+pub struct Search<'a> {
+    pub limits: SearchLimits,
+    pub table: &'a mut TranspositionTable,
+    /// How to judge positions, `eval::Classic` if `None`
+    pub evaluator: Option<&'a mut dyn Evaluator>,
+    /// Set this to true, for example from another thread, to stop the search
+    pub exit: Option<&'a AtomicBool>,
+    /// Killer moves and history, kept between iterations like the table
+    ordering: MoveOrdering
+}
+impl<'a> Search<'a> {
+    /// Create a new search using the specified limits and table
+    pub fn new(limits: SearchLimits, table: &'a mut TranspositionTable) -> Self {
+        Self {
+            limits,
+            table,
+            evaluator: None,
+            exit: None,
+            ordering: MoveOrdering::new()
+        }
+    }
+
+    /// Search for the best move for the side to move, scoring repetitions of
+    /// any of the positions in `history` (oldest first) as draws. `callback`
+    /// is called after each completed iteration.
+    ///
+    /// Returns the result of the last completed iteration. The first
+    /// iteration is always completed unless the search is stopped through the
+    /// exit flag, so this only returns `None` then, or if there are no legal
+    /// moves.
+    pub fn run<F>(&mut self, board: &mut Board, history: &[u64], mut callback: F) -> Option<MinimaxResult>
+        where F: FnMut(&Iteration)
+    {
+        let start = Instant::now();
+        let limits = self.limits;
+
+        let max_depth = match limits.depth {
+            Some(depth) if !limits.infinite => depth.max(1).min(MAX_DEPTH),
+            _ => MAX_DEPTH
+        };
+        let deadline = match (limits.deadline, limits.move_time.map(|time| start + time)) {
+            _ if limits.infinite => None,
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b)
+        };
+
+        let mut classic = Classic;
+        let evaluator: &mut dyn Evaluator = match &mut self.evaluator {
+            Some(evaluator) => &mut **evaluator,
+            None => &mut classic
+        };
+
+        let mut stats = SearchStats::default();
+        let mut best = None;
+        self.ordering.new_search();
+
+        for depth in 1..=max_depth {
+            let mut inner = Limits::default();
+            if depth > 1 {
+                inner.deadline = deadline;
+                if !limits.infinite {
+                    inner.nodes = limits.nodes.map(|max| max.saturating_sub(stats.nodes + stats.qnodes));
+                }
+            }
+            let result = match board.minimax_limited(depth, history, self.table, &mut self.ordering, &mut *evaluator, self.exit, inner) {
+                Some(result) => result,
+                None => break
+            };
+
+            stats.nodes += result.stats.nodes;
+            stats.qnodes += result.stats.qnodes;
+            callback(&Iteration {
+                result: &result,
+                stats,
+                elapsed: start.elapsed()
+            });
+
+            // A forced mate won't get any better by searching deeper
+            let mate = result.score.abs() >= MATE - depth as i32;
+            best = Some(result);
+            if limits.infinite {
+                continue;
+            }
+            if mate || limits.soft_time.map(|soft| start.elapsed() >= soft).unwrap_or(false) {
+                break;
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    /// Run a search from the starting position, check that it returns a
+    /// legal move, and return the depths of all completed iterations
+    fn search_with_exit(limits: SearchLimits, exit: Option<&AtomicBool>) -> Vec<u8> {
+        let mut board = Board::default();
+        let mut table = TranspositionTable::new(1);
+        let mut search = Search::new(limits, &mut table);
+        search.exit = exit;
+        let mut depths = Vec::new();
+        let result = search.run(&mut board, &[], |iteration| depths.push(iteration.result.depth));
+        assert_eq!(result.as_ref().map(|result| result.depth), depths.last().cloned());
+        if let Some(result) = result {
+            assert!(board.is_legal(result.move_));
+        }
+        depths
+    }
+    fn search(limits: SearchLimits) -> Vec<u8> {
+        search_with_exit(limits, None)
+    }
+
+    #[test]
+    fn depth_limit() {
+        assert_eq!(search(SearchLimits::depth(3)), [1, 2, 3]);
+        assert_eq!(search(SearchLimits::depth(1)), [1]);
+        // There's always a move to play if there are legal moves
+        assert_eq!(search(SearchLimits::depth(0)), [1]);
+    }
+    #[test]
+    fn node_limit() {
+        let limits = SearchLimits {
+            nodes: Some(1),
+            ..SearchLimits::default()
+        };
+        assert_eq!(search(limits), [1]);
+    }
+    #[test]
+    fn time_limits() {
+        let depths = search(SearchLimits::move_time(Duration::from_millis(5)));
+        assert!(!depths.is_empty() && depths.len() < MAX_DEPTH as usize);
+
+        let limits = SearchLimits {
+            deadline: Some(Instant::now()),
+            ..SearchLimits::default()
+        };
+        assert_eq!(search(limits), [1]);
+
+        let limits = SearchLimits {
+            soft_time: Some(Duration::from_secs(0)),
+            ..SearchLimits::default()
+        };
+        assert_eq!(search(limits), [1]);
+    }
+    #[test]
+    fn exit() {
+        // Nothing is searched, so there's no move to return
+        let exit = AtomicBool::new(true);
+        assert!(search_with_exit(SearchLimits::depth(3), Some(&exit)).is_empty());
+
+        // An infinite search ignores the other limits until told to stop
+        let exit = AtomicBool::new(false);
+        let mut board = Board::default();
+        let mut table = TranspositionTable::new(1);
+        let limits = SearchLimits {
+            depth: Some(1),
+            nodes: Some(1),
+            ..SearchLimits::infinite()
+        };
+        let mut search = Search::new(limits, &mut table);
+        search.exit = Some(&exit);
+        let result = search.run(&mut board, &[], |iteration| if iteration.result.depth == 3 {
+            exit.store(true, Ordering::SeqCst);
+        });
+        let result = result.unwrap();
+        assert_eq!(result.depth, 3);
+        assert!(board.is_legal(result.move_));
+    }
+}
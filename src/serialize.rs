@@ -11,8 +11,59 @@ const BLACK_CASTLING_QUEENSIDE: u8 = 1 << 1;
 const BLACK_CASTLING_KINGSIDE:  u8 = 1 << 2;
 const WHITE_CASTLING_QUEENSIDE: u8 = 1 << 3;
 const WHITE_CASTLING_KINGSIDE:  u8 = 1 << 4;
+const BLACK_TO_MOVE:            u8 = 1 << 5;
 
-/// Serialize a board to an I/O stream
+/// Identifies the versioned format. The first byte can't be mistaken for the
+/// flags byte the unversioned format starts with.
+pub const MAGIC: [u8; 4] = *b"\x89CMB";
+/// The version of the format written by `serialize_board`
+pub const VERSION: u8 = 1;
+/// All flags the unversioned format can have set
+const LEGACY_FLAGS: u8 = (1 << 6) - 1;
+/// Stored in place of the en passant position if there is none
+const NO_EN_PASSANT: u8 = 0xFF;
+
+/// An error reading a board
+#[derive(Debug, Fail)]
+pub enum DeserializeError {
+    #[fail(display = "failed to read board: {}", _0)]
+    Io(#[cause] io::Error),
+    #[fail(display = "not a saved board, the header is {:02x?}", _0)]
+    BadMagic([u8; 4]),
+    #[fail(display = "unsupported board format version {}", _0)]
+    UnsupportedVersion(u8),
+    #[fail(display = "board checksum mismatch, the data is corrupt")]
+    ChecksumMismatch,
+    #[fail(display = "invalid flags {:#010b}", _0)]
+    InvalidFlags(u8),
+    #[fail(display = "invalid en passant position {}", _0)]
+    InvalidEnPassant(u8),
+    #[fail(display = "invalid piece byte {:#04x}", _0)]
+    InvalidPieces(u8),
+    #[fail(display = "invalid fullmove number 0")]
+    InvalidClock
+}
+impl From<io::Error> for DeserializeError {
+    fn from(err: io::Error) -> Self {
+        DeserializeError::Io(err)
+    }
+}
+
+/// Calculate the CRC-32 checksum used by zlib and PNG
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Serialize a board to an I/O stream. The format starts with `MAGIC` and
+/// `VERSION`, followed by flags, the en passant position, both clocks, the
+/// pieces packed two per byte, and a CRC-32 checksum of everything before it.
 pub fn serialize_board<W: Write>(out: &mut W, board: &Board) -> io::Result<()> {
     let mut flags = 0;
     if board.en_passant.is_some() {
@@ -22,48 +73,105 @@ pub fn serialize_board<W: Write>(out: &mut W, board: &Board) -> io::Result<()> {
     if board.castling_black.kingside { flags |= BLACK_CASTLING_KINGSIDE; }
     if board.castling_white.queenside { flags |= WHITE_CASTLING_QUEENSIDE; }
     if board.castling_white.kingside { flags |= WHITE_CASTLING_KINGSIDE; }
-    out.write_all(&[flags])?;
+    if board.side_to_move == Side::Black { flags |= BLACK_TO_MOVE; }
 
-    if let Some(en_passant) = board.en_passant {
-        out.write_all(&[serialize_pos(en_passant)])?;
-    }
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.push(flags);
+    bytes.push(board.en_passant.map(serialize_pos).unwrap_or(NO_EN_PASSANT));
+    bytes.extend_from_slice(&board.halfmove_clock.to_be_bytes());
+    bytes.extend_from_slice(&board.fullmove_number.to_be_bytes());
 
     for row in board {
         for pieces in row.chunks(2) {
             let mut pieces_array = [None; 2];
             pieces_array.copy_from_slice(pieces);
-            let byte = serialize_pieces(&pieces_array);
-            out.write_all(&[byte])?;
+            bytes.push(serialize_pieces(&pieces_array));
         }
     }
-    Ok(())
+
+    let checksum = crc32(&bytes);
+    bytes.extend_from_slice(&checksum.to_be_bytes());
+    out.write_all(&bytes)
 }
-/// Deserialize a board from an I/O stream
-pub fn deserialize_board<R: Read>(input: &mut R) -> io::Result<Board> {
+/// Deserialize a board from an I/O stream. Besides the current format, this
+/// also reads boards saved before it was versioned, which have no magic,
+/// clocks or checksum.
+pub fn deserialize_board<R: Read>(input: &mut R) -> Result<Board, DeserializeError> {
     let mut byte = [0];
     input.read_exact(&mut byte)?;
-    let flags = byte[0];
+    if byte[0] != MAGIC[0] {
+        return deserialize_legacy(input, byte[0]);
+    }
+
+    let mut header = [0; 5];
+    input.read_exact(&mut header)?;
+    if header[..3] != MAGIC[1..] {
+        return Err(DeserializeError::BadMagic([byte[0], header[0], header[1], header[2]]));
+    }
+    let version = header[3];
+    if version != VERSION {
+        return Err(DeserializeError::UnsupportedVersion(version));
+    }
+    let flags = header[4];
+
+    let mut body = [0; 5 + (board::WIDTH * board::WIDTH / 2) as usize];
+    input.read_exact(&mut body)?;
+    let mut checksum = [0; 4];
+    input.read_exact(&mut checksum)?;
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(version);
+    bytes.push(flags);
+    bytes.extend_from_slice(&body);
+    if crc32(&bytes) != u32::from_be_bytes(checksum) {
+        return Err(DeserializeError::ChecksumMismatch);
+    }
 
+    let en_passant = match body[0] {
+        NO_EN_PASSANT => None,
+        byte => Some(deserialize_pos(byte).ok_or(DeserializeError::InvalidEnPassant(byte))?)
+    };
+    if en_passant.is_some() != (flags & HAS_EN_PASSANT == HAS_EN_PASSANT) {
+        return Err(DeserializeError::InvalidEnPassant(body[0]));
+    }
+    let mut board = board_from_parts(flags, en_passant, &body[5..])?;
+    board.halfmove_clock = u16::from_be_bytes([body[1], body[2]]);
+    board.fullmove_number = u16::from_be_bytes([body[3], body[4]]);
+    if board.fullmove_number == 0 {
+        return Err(DeserializeError::InvalidClock);
+    }
+    Ok(board)
+}
+/// Read the rest of a board in the unversioned format, which starts with the
+/// flags byte and an optional en passant position
+fn deserialize_legacy<R: Read>(input: &mut R, flags: u8) -> Result<Board, DeserializeError> {
+    let mut byte = [0];
     let en_passant = if flags & HAS_EN_PASSANT == HAS_EN_PASSANT {
         input.read_exact(&mut byte)?;
-        deserialize_pos(byte[0])
+        Some(deserialize_pos(byte[0]).ok_or(DeserializeError::InvalidEnPassant(byte[0]))?)
     } else {
         None
     };
 
+    let mut pieces = [0; (board::WIDTH * board::WIDTH / 2) as usize];
+    input.read_exact(&mut pieces)?;
+    board_from_parts(flags, en_passant, &pieces)
+}
+/// Build a board from the flags and pieces, which both formats share
+fn board_from_parts(flags: u8, en_passant: Option<Pos>, bytes: &[u8]) -> Result<Board, DeserializeError> {
+    if flags & !LEGACY_FLAGS != 0 {
+        return Err(DeserializeError::InvalidFlags(flags));
+    }
+
     let mut pieces = [[None; board::WIDTH as usize]; board::WIDTH as usize];
-    for y in 0..board::WIDTH as usize {
-        let mut bytes = [0; board::WIDTH as usize / 2];
-        input.read_exact(&mut bytes)?;
-
-        let mut x = 0;
-        for byte in &bytes {
-            let p = deserialize_pieces(*byte);
-            pieces[y][x..x+2].copy_from_slice(&p);
-            x += 2;
+    for (row, bytes) in pieces.iter_mut().zip(bytes.chunks(board::WIDTH as usize / 2)) {
+        for (squares, &byte) in row.chunks_mut(2).zip(bytes) {
+            let p = deserialize_pieces(byte).ok_or(DeserializeError::InvalidPieces(byte))?;
+            squares.copy_from_slice(&p);
         }
     }
-    Ok(Board {
+    let mut board = Board {
         pieces,
         en_passant,
         castling_black: Castling {
@@ -73,8 +181,15 @@ pub fn deserialize_board<R: Read>(input: &mut R) -> io::Result<Board> {
         castling_white: Castling {
             queenside: flags & WHITE_CASTLING_QUEENSIDE == WHITE_CASTLING_QUEENSIDE,
             kingside:  flags & WHITE_CASTLING_KINGSIDE  == WHITE_CASTLING_KINGSIDE
-        }
-    })
+        },
+        side_to_move: if flags & BLACK_TO_MOVE == BLACK_TO_MOVE { Side::Black } else { Side::White },
+        ..Board::default()
+    };
+    // The same position read from FEN has to end up with the same key
+    board.drop_unusable_rights();
+    board.zobrist = board.compute_zobrist();
+    board.pawn_zobrist = board.compute_pawn_key();
+    Ok(board)
 }
 
 /// Serialize a position into a byte
@@ -110,27 +225,156 @@ pub fn serialize_pieces(pieces: &[Option<Piece>; 2]) -> u8 {
     }
     byte
 }
-/// Deserialize a byte into 2 pieces
-pub fn deserialize_pieces(byte: u8) -> [Option<Piece>; 2] {
+/// Deserialize a byte into 2 pieces, or `None` if it doesn't describe two
+/// valid squares
+pub fn deserialize_pieces(byte: u8) -> Option<[Option<Piece>; 2]> {
     let mut pieces = [None; 2];
-    for i in 0..2 {
-        let mut byte = byte >> ((1 - i) * 4);
-        let side = if byte & 1 == 1 {
+    for (i, piece) in pieces.iter_mut().enumerate() {
+        let nibble = byte >> ((1 - i) * 4) & 0b1111;
+        let side = if nibble & 1 == 1 {
             Side::White
         } else {
             Side::Black
         };
-        byte >>= 1;
-        pieces[i] = match byte & 0b111 {
-            1 => Some(Piece { kind: PieceKind::Pawn, side }),
-            2 => Some(Piece { kind: PieceKind::Knight, side }),
-            3 => Some(Piece { kind: PieceKind::Bishop, side }),
-            4 => Some(Piece { kind: PieceKind::Rook, side }),
-            5 => Some(Piece { kind: PieceKind::Queen, side }),
-            6 => Some(Piece { kind: PieceKind::King, side }),
-            // Zero or invalid
-            _ => None
+        let kind = match nibble >> 1 {
+            0 if side == Side::Black => continue,
+            1 => PieceKind::Pawn,
+            2 => PieceKind::Knight,
+            3 => PieceKind::Bishop,
+            4 => PieceKind::Rook,
+            5 => PieceKind::Queen,
+            6 => PieceKind::King,
+            _ => return None
         };
+        *piece = Some(Piece { kind, side });
+    }
+    Some(pieces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(fen: &str) {
+        let board = Board::from_fen(fen).unwrap();
+        let mut bytes = Vec::new();
+        serialize_board(&mut bytes, &board).unwrap();
+        assert_eq!(&bytes[..4], &MAGIC);
+        assert_eq!(bytes[4], VERSION);
+        let read = deserialize_board(&mut &bytes[..]).unwrap();
+        assert_eq!(read.to_fen(), fen);
+        assert_eq!(read.position_key(), board.position_key());
+    }
+
+    #[test]
+    fn versioned_round_trip() {
+        round_trip("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        round_trip("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b Kq e3 0 3");
+        round_trip("8/2k5/8/8/8/8/5K2/8 w - - 99 300");
+        round_trip("r3k2r/8/8/8/8/8/8/R3K2R b - - 5 1000");
+    }
+    #[test]
+    fn corrupt_checksum() {
+        let mut bytes = Vec::new();
+        serialize_board(&mut bytes, &Board::default()).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        match deserialize_board(&mut &bytes[..]) {
+            Err(DeserializeError::ChecksumMismatch) => (),
+            result => panic!("expected a checksum mismatch, got {:?}", result)
+        }
+
+        // Corrupting the data instead is caught the same way
+        bytes[last] ^= 1;
+        bytes[20] ^= 0x10;
+        match deserialize_board(&mut &bytes[..]) {
+            Err(DeserializeError::ChecksumMismatch) => (),
+            result => panic!("expected a checksum mismatch, got {:?}", result)
+        }
+    }
+    #[test]
+    fn invalid_header() {
+        let mut bytes = Vec::new();
+        serialize_board(&mut bytes, &Board::default()).unwrap();
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[2] = b'X';
+        match deserialize_board(&mut &bad_magic[..]) {
+            Err(DeserializeError::BadMagic(magic)) => assert_eq!(&magic, b"\x89CXB"),
+            result => panic!("expected a bad magic, got {:?}", result)
+        }
+
+        let mut future = bytes.clone();
+        future[4] = VERSION + 1;
+        match deserialize_board(&mut &future[..]) {
+            Err(DeserializeError::UnsupportedVersion(version)) => assert_eq!(version, VERSION + 1),
+            result => panic!("expected an unsupported version, got {:?}", result)
+        }
+
+        match deserialize_board(&mut &bytes[..bytes.len() - 1]) {
+            Err(DeserializeError::Io(_)) => (),
+            result => panic!("expected an I/O error, got {:?}", result)
+        }
+    }
+    #[test]
+    fn legacy_starting_position() {
+        // The starting position, as saved before the format was versioned
+        let bytes = [
+            0b0001_1110,
+            0x84, 0x6A, 0xC6, 0x48,
+            0x22, 0x22, 0x22, 0x22,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+            0x33, 0x33, 0x33, 0x33,
+            0x95, 0x7B, 0xD7, 0x59
+        ];
+        let board = deserialize_board(&mut &bytes[..]).unwrap();
+        assert_eq!(board.to_fen(), Board::default().to_fen());
+        assert_eq!(board.position_key(), Board::default().position_key());
+    }
+    #[test]
+    fn legacy_en_passant() {
+        let board = Board::from_fen("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+
+        // Written the way the unversioned format was: flags, the en passant
+        // position if there is one, and the pieces
+        let mut bytes = vec![HAS_EN_PASSANT | 0b0001_1110 | BLACK_TO_MOVE];
+        bytes.push(serialize_pos(board.en_passant.unwrap()));
+        for row in &board {
+            for pieces in row.chunks(2) {
+                bytes.push(serialize_pieces(&[pieces[0], pieces[1]]));
+            }
+        }
+        assert_eq!(bytes.len(), 2 + 32);
+
+        let read = deserialize_board(&mut &bytes[..]).unwrap();
+        assert_eq!(read.to_fen(), board.to_fen());
+        assert_eq!(read.position_key(), board.position_key());
+
+        // Truncated streams are an error rather than a half empty board
+        assert!(deserialize_board(&mut &bytes[..20]).is_err());
+    }
+    #[test]
+    fn unusable_rights() {
+        // All castling rights and an en passant pawn, but only the white
+        // queenside castling has its king and rook and no pawn can capture
+        let fen = "4k3/8/8/8/4P3/8/8/R3K3 b KQkq e3 0 1";
+        let board = Board::from_fen(fen).unwrap();
+        assert_eq!(board.to_fen(), "4k3/8/8/8/4P3/8/8/R3K3 b Q - 0 1");
+
+        let mut bytes = vec![LEGACY_FLAGS];
+        bytes.push(serialize_pos(Pos(4, 4)));
+        for row in &board {
+            for pieces in row.chunks(2) {
+                bytes.push(serialize_pieces(&[pieces[0], pieces[1]]));
+            }
+        }
+
+        let read = deserialize_board(&mut &bytes[..]).unwrap();
+        assert_eq!(read.to_fen(), board.to_fen());
+        assert_eq!(read.position_key(), board.position_key());
+        assert_eq!(read.legal_moves(Side::Black).len(), board.legal_moves(Side::Black).len());
     }
-    pieces
 }
@@ -1,22 +1,31 @@
 use crate::{
-    board::{Board, Change},
+    board::{Board, Move},
+    clock::{Clock, TimeControl},
+    eval::{Classic, Evaluation, Evaluator},
+    game::{Game, GameOutcome},
+    minimax::MinimaxResult,
+    pgn::{self, PgnGame},
     piece::PieceKind,
+    search::{Iteration, Search, SearchLimits},
     serialize,
+    transposition::TranspositionTable,
     Pos,
     Side
 };
 use std::{
     collections::HashSet,
-    fs::{File, OpenOptions},
+    fs::{self, File, OpenOptions},
     io::{self, Write},
 };
 #[cfg(feature = "terminal-bin")]
 use std::{
+    mem,
     sync::{atomic::{AtomicBool, Ordering}, Arc},
     thread
 };
 
 const BOARD_FILE: &'static str = "saved_board";
+#[cfg(not(feature = "terminal-bin"))]
 const DEPTH: u8 = 5;
 
 // Not using termion because I REALLY want to be able to use this front-end in
@@ -32,12 +41,30 @@ const WHITE_BG: &str = "\x1b[47m";
 const WHITE_FG: &str = "\x1b[37m";
 const YELLOW_BG: &str = "\x1b[43m";
 
+/// Join the moves of a principal variation played from `board` with spaces
+fn format_pv(board: &Board, pv: &[Move]) -> String {
+    board.line_to_san(pv).join(" ")
+}
+/// Print the progress of a search from `board` after one iteration
+fn print_iteration<W: Write>(out: &mut W, board: &Board, iteration: &Iteration) -> io::Result<()> {
+    let result = iteration.result;
+    writeln!(out, "depth {}, score {}: {}", result.depth, result.score, format_pv(board, &result.pv))?;
+    writeln!(out, "searched {} nodes, {} in quiescence", iteration.stats.nodes, iteration.stats.qnodes)
+}
+
 pub struct Session<W: Write> {
     pub out: W,
-    pub board: Board,
-    pub side: Side,
-    pub undo: Vec<Change>,
-    pub highlight: HashSet<Pos>
+    pub game: Game,
+    pub highlight: HashSet<Pos>,
+    /// The side shown at the bottom of the board, toggled by `rotate`
+    pub orientation: Side,
+    /// Kept across moves so the engine can reuse earlier results
+    pub table: TranspositionTable,
+    pub clock: Option<Clock>,
+    /// Which evaluation the engine plays with
+    pub evaluation: Evaluation,
+    /// An evaluator of that kind, kept across moves so its caches are reused
+    pub evaluator: Box<dyn Evaluator + Send>
 }
 impl<W: Write> Session<W> {
     pub fn check_status(&mut self, side: Side) -> io::Result<()> {
@@ -45,34 +72,33 @@ impl<W: Write> Session<W> {
             Side::Black => "black",
             Side::White => "white"
         };
-        if self.board.iter()
+        if self.game.board().iter()
                 .flat_map(|row| row.iter())
                 .filter_map(|piece| piece.as_ref())
                 .all(|p| p.kind != PieceKind::King || p.side != side) {
             writeln!(self.out, "{}{} has no king{}", ITALIC, side_str, RESET)?;
-        } else if self.board.is_checkmate(side) {
-            writeln!(self.out, "{}{} is checkmated{}", ITALIC, side_str, RESET)?;
-        } else if let Some(pos) = self.board.check(side) {
+        } else if let Some(pos) = self.game.board().check(side) {
             writeln!(self.out, "{}{} is checked by {}{}", ITALIC, side_str, pos, RESET)?;
         }
         Ok(())
     }
     pub fn draw(&mut self) -> io::Result<()> {
         write!(self.out, "  {}{}", RED_FG, BOLD)?;
-        let iter: Box<Iterator<Item = _>> = match self.side {
+        let side = self.orientation;
+        let iter: Box<Iterator<Item = _>> = match side {
             Side::White => {
                 write!(self.out, " A  B  C  D  E  F  G  H")?;
-                Box::new(self.board.iter().enumerate())
+                Box::new(self.game.board().iter().enumerate())
             },
             Side::Black => {
                 write!(self.out, " H  G  F  E  D  C  B  A")?;
-                Box::new(self.board.iter().enumerate().rev())
+                Box::new(self.game.board().iter().enumerate().rev())
             }
         };
         writeln!(self.out, "{}", RESET)?;
         for (y, row) in iter {
             write!(self.out, "{}{}{}{}{} ", BOLD, RED_FG, 8-y, RESET, BOLD)?;
-            let iter: Box<Iterator<Item = _>> = match self.side {
+            let iter: Box<Iterator<Item = _>> = match side {
                 Side::White => Box::new(row.into_iter().enumerate()),
                 Side::Black => Box::new(row.into_iter().enumerate().rev()),
             };
@@ -95,14 +121,31 @@ impl<W: Write> Session<W> {
         self.check_status(Side::Black)?;
         self.check_status(Side::White)?;
 
+        if let Some(outcome) = self.game.outcome() {
+            writeln!(self.out, "{}game over: {}{}", BOLD, outcome, RESET)?;
+        }
+        if let Some(clock) = &self.clock {
+            writeln!(self.out, "{}clock: {}{}", ITALIC, clock, RESET)?;
+            if let Some(side) = clock.flagged() {
+                writeln!(self.out, "{}game over: {}{}", BOLD, GameOutcome::Timeout { winner: !side }, RESET)?;
+            }
+        }
+
         writeln!(self.out, "{}Possible commands: \
             all, \
+            clock, \
+            eval, \
+            evaluation, \
             go, \
             load, \
+            loadpgn, \
             move(f), \
+            pass, \
+            perft, \
             possible, \
             rotate, \
             save, \
+            savepgn, \
             score, \
             undo\
             {}", ITALIC, RESET)?;
@@ -112,24 +155,45 @@ impl<W: Write> Session<W> {
     pub fn possible(&mut self, from: Pos) -> io::Result<()> {
         write!(self.out, "{}: ", from)?;
         let mut first = true;
-        let mut moves = self.board.moves_for(from);
-        while let Some(to) = moves.next(&mut self.board) {
-            self.highlight.insert(to);
+        for m in self.game.board().legal_moves_from(from) {
+            self.highlight.insert(m.to);
             if !first {
                 write!(self.out, ", ")?;
             }
             first = false;
-            write!(self.out, "{}", to)?;
-        };
+            write!(self.out, "{}", m.to)?;
+            if let Some(kind) = m.promotion {
+                write!(self.out, "={}", kind.letter())?;
+            }
+        }
         writeln!(self.out)?;
         Ok(())
     }
+    /// Start the clock of the side to move, if there is a clock
+    fn update_clock(&mut self) {
+        if let Some(clock) = &mut self.clock {
+            clock.start(self.game.board().side_to_move());
+        }
+    }
+    /// Search for the best move within the specified limits, printing the
+    /// progress
+    fn search(&mut self, limits: SearchLimits) -> io::Result<Option<MinimaxResult>> {
+        let mut search = Search::new(limits, &mut self.table);
+        search.evaluator = Some(&mut *self.evaluator);
+        let board = self.game.board().clone();
+        let out = &mut self.out;
+        let mut output = Ok(());
+        let res = self.game.search(&mut search, |iteration| if output.is_ok() {
+            output = print_iteration(out, &board, iteration);
+        });
+        output.map(|()| res)
+    }
     pub fn command(&mut self, line: &str) -> io::Result<()> {
         self.highlight.clear();
 
         macro_rules! println {
             ($($arg:expr),*) => {
-                writeln!(self.out$(, $arg)*)?;
+                writeln!(self.out$(, $arg)*)?
             }
         }
         macro_rules! expect {
@@ -165,85 +229,137 @@ impl<W: Write> Session<W> {
             Some("all") => {
                 expect!(args.is_empty(), "all");
 
-                let mut pieces = self.board.pieces(self.side);
-                while let Some((pos, _)) = pieces.next(&self.board) {
+                let mut pieces = self.game.board().pieces(self.game.board().side_to_move());
+                while let Some((pos, _)) = pieces.next(self.game.board()) {
                     self.possible(pos)?;
                 }
             },
+            Some("move") if args.len() == 1 => {
+                let m = expect!(self.game.board().parse_move(args[0]));
+                self.game.make_move(m);
+                self.update_clock();
+            },
             Some("move") | Some("movef") => {
-                expect!(args.len() == 2, "move(f) <from> <to>");
+                expect!(args.len() == 2 || args.len() == 3, "move <san> | move(f) <from> <to> [promotion]");
 
                 let force = cmd == Some("movef");
 
                 let from = expect!(args[0].parse());
                 let to = expect!(args[1].parse());
+                let promotion = match args.get(2) {
+                    None => None,
+                    Some(arg) => {
+                        let kind = arg.chars().next()
+                            .map(|c| c.to_ascii_uppercase())
+                            .and_then(PieceKind::from_letter)
+                            .filter(|&kind| kind != PieceKind::Pawn && kind != PieceKind::King);
+                        expect!(kind.is_some() && arg.len() == 1, "promotion must be one of N, B, R, Q");
+                        kind
+                    }
+                };
+                let board = self.game.board();
+                let m = board.infer_move(from, to, promotion);
 
-                if !force {
+                if !force && !board.is_legal(m) {
                     let mut possible = false;
-                    let mut moves = self.board.moves_for(from);
-                    while let Some(m) = moves.next(&mut self.board) {
-                        if m == to {
+                    let mut moves = board.moves_for(from);
+                    while let Some(other) = moves.next(board) {
+                        if other == m {
                             possible = true;
                             break;
                         }
                     }
                     expect!(possible, "piece can't move there (hint: movef)");
-                }
 
-                let undo = self.board.move_(from, to);
-                if !force {
-                    if let Some(pos) = self.board.check(!self.side) {
-                        self.board.undo(undo);
+                    if let Some(pos) = board.check(board.side_to_move()) {
                         self.highlight.insert(pos);
-                        println!("can't place yourself in check!");
-                        return Ok(());
                     }
+                    println!("can't place yourself in check!");
+                    return Ok(());
                 }
-                self.undo.push(undo);
+
+                self.game.make_move(m);
+                self.update_clock();
             },
             Some("undo") => {
                 expect!(args.is_empty(), "undo");
 
-                match self.undo.pop() {
-                    Some(change) => self.board.undo(change),
-                    None => {
-                        println!("no recent move to undo");
-                    }
+                if self.game.undo().is_none() {
+                    println!("no recent move to undo");
+                } else if let Some(clock) = &mut self.clock {
+                    // Taking a move back refunds it, and the move in
+                    // progress doesn't cost anyone time
+                    clock.take_back(self.game.board().side_to_move());
                 }
             },
             Some("score") => {
-                println!("Black score: {}", self.board.score(Side::Black));
-                println!("White score: {}", self.board.score(Side::White));
+                println!("Black score: {}", self.game.board().score(Side::Black));
+                println!("White score: {}", self.game.board().score(Side::White));
+            },
+            Some("eval") => {
+                expect!(args.is_empty(), "eval");
+
+                let breakdown = self.evaluator.breakdown(self.game.board());
+                println!("Evaluation: {}", self.evaluation);
+                println!("{}", breakdown);
+            },
+            Some("evaluation") => {
+                expect!(args.len() <= 1, "evaluation [classic|tapered]");
+
+                match args.first() {
+                    None => {
+                        println!("{}", self.evaluation);
+                    },
+                    Some(name) => {
+                        let evaluation = expect!(Evaluation::from_name(name).ok_or("unknown evaluation"));
+                        self.evaluation = evaluation;
+                        self.evaluator = evaluation.evaluator();
+                    }
+                }
             },
             Some("go") => {
                 expect!(args.is_empty(), "go");
 
+                let side = self.game.board().side_to_move();
+
                 #[cfg(not(feature = "terminal-bin"))]
                 let res = {
-                    self.board.minimax(DEPTH, self.side, None)
+                    let limits = match &self.clock {
+                        Some(clock) => clock.budget(side).limits(),
+                        None => SearchLimits::depth(DEPTH)
+                    };
+                    self.search(limits)?
                 };
                 #[cfg(feature = "terminal-bin")]
-                let res = {
+                let res = if let Some(clock) = &self.clock {
+                    // Let the clock decide when to stop
+                    let limits = clock.budget(side).limits();
+                    self.search(limits)?
+                } else {
                     let exit = Arc::new(AtomicBool::new(false));
 
                     println!("Calculating, press ENTER to stop:");
 
                     let thread = {
-                        let side = self.side;
-                        let mut board = self.board.clone();
+                        let mut game = self.game.clone();
+                        // Lend the table and evaluator to the thread, they're
+                        // given back once done
+                        let mut table = mem::replace(&mut self.table, TranspositionTable::new(0));
+                        let mut evaluator = mem::replace(&mut self.evaluator, Box::new(Classic));
                         let exit = Arc::clone(&exit);
-                        thread::spawn(move || -> io::Result<_> {
-                            let mut res = None;
-                            for i in DEPTH - 3.. {
-                                writeln!(io::stdout(), "Trying depth {}...", i)?;
-                                if let Some(new) = board.minimax(i, side, Some(&exit)) {
-                                    res = Some((i, new));
-                                }
-                                if exit.load(Ordering::SeqCst) {
-                                    break;
-                                }
-                            }
-                            Ok(res)
+                        thread::spawn(move || {
+                            let res = {
+                                let mut search = Search::new(SearchLimits::infinite(), &mut table);
+                                search.evaluator = Some(&mut *evaluator);
+                                search.exit = Some(&exit);
+                                let board = game.board().clone();
+                                let mut output = Ok(());
+                                let res = game.search(&mut search, |iteration| if output.is_ok() {
+                                    output = print_iteration(&mut io::stdout(), &board, iteration);
+                                });
+                                output.map(|()| res)
+                            };
+                            (res, table, evaluator)
                         })
                     };
 
@@ -253,9 +369,12 @@ impl<W: Write> Session<W> {
                     println!("Stopping...");
                     exit.store(true, Ordering::SeqCst);
 
-                    match thread.join().unwrap()? {
-                        Some((depth, res)) => {
-                            println!("searched at depth {}", depth);
+                    let (res, table, evaluator) = thread.join().unwrap();
+                    self.table = table;
+                    self.evaluator = evaluator;
+                    match res? {
+                        Some(res) => {
+                            println!("searched at depth {}", res.depth);
                             Some(res)
                         },
                         None => {
@@ -265,16 +384,25 @@ impl<W: Write> Session<W> {
                     }
                 };
                 if let Some(res) = res {
-                    let undo = self.board.move_(res.from, res.to);
-                    self.undo.push(undo);
-                    println!("move {} to {}", res.from, res.to);
+                    let san = self.game.board().to_san(res.move_);
+                    let line = format_pv(self.game.board(), &res.pv);
+                    self.game.make_move(res.move_);
+                    self.update_clock();
+                    println!("move {}", san);
                     println!("final score: {}", res.score);
+                    println!("expected line: {}", line);
                 }
             },
+            Some("pass") => {
+                expect!(args.is_empty(), "pass");
+
+                self.game.pass_turn();
+                self.update_clock();
+            },
             Some("rotate") => {
                 expect!(args.is_empty(), "rotate");
 
-                self.side = !self.side;
+                self.orientation = !self.orientation;
             },
             Some("save") => {
                 expect!(args.is_empty(), "save");
@@ -283,13 +411,66 @@ impl<W: Write> Session<W> {
                     .create_new(true)
                     .write(true)
                     .open(BOARD_FILE)?;
-                serialize::serialize_board(&mut file, &self.board)?;
+                serialize::serialize_board(&mut file, self.game.board())?;
             },
             Some("load") => {
                 expect!(args.is_empty(), "load");
 
                 let mut file = File::open(BOARD_FILE)?;
-                self.board = serialize::deserialize_board(&mut file)?;
+                self.game = Game::new(expect!(serialize::deserialize_board(&mut file)));
+                self.update_clock();
+            },
+            Some("perft") => {
+                expect!(args.len() == 1, "perft <depth>");
+
+                let depth = expect!(args[0].parse::<u8>());
+                expect!(depth >= 1, "depth must be at least 1");
+
+                let mut board = self.game.board().clone();
+                let mut total = 0;
+                for (m, nodes) in board.divide(depth) {
+                    println!("{}: {}", self.game.board().to_san(m), nodes);
+                    total += nodes;
+                }
+                println!("total: {}", total);
+            },
+            Some("savepgn") => {
+                expect!(args.len() == 1, "savepgn <file>");
+
+                let pgn = PgnGame::from_game(&self.game);
+                fs::write(args[0], pgn.to_string())?;
+            },
+            Some("loadpgn") => {
+                expect!(args.len() == 1 || args.len() == 2, "loadpgn <file> [game number]");
+
+                let index = match args.get(1) {
+                    None => 1,
+                    Some(arg) => expect!(arg.parse::<usize>())
+                };
+                let games = expect!(pgn::parse(&fs::read_to_string(args[0])?));
+                expect!(index >= 1 && index <= games.len(), "no game with that number in file");
+                self.game = expect!(games[index - 1].game());
+                self.update_clock();
+            },
+            Some("clock") => {
+                expect!(args.len() <= 1, "clock [off|<time control, like 5, 5+3, 5d3 or 40/90>]");
+
+                match args.first() {
+                    None => match &self.clock {
+                        Some(clock) => {
+                            println!("{} ({})", clock, clock.control());
+                        },
+                        None => {
+                            println!("no clock");
+                        }
+                    },
+                    Some(&"off") => self.clock = None,
+                    Some(control) => {
+                        let control: TimeControl = expect!(control.parse());
+                        self.clock = Some(Clock::new(control));
+                        self.update_clock();
+                    }
+                }
             },
             Some(_) => println!("unknown command"),
         }
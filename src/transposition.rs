@@ -0,0 +1,197 @@
+use crate::board::Move;
+use std::mem;
+
+/// The size of a transposition table in megabytes when none is specified
+pub const DEFAULT_SIZE: usize = 16;
+
+/// How a stored score relates to the real score of a position
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    /// The score is exact
+    Exact,
+    /// The real score is at least this, a move caused a beta cutoff
+    Lower,
+    /// The real score is at most this, no move raised alpha
+    Upper
+}
+
+/// The result of searching a position, stored for when it's seen again
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub key: u64,
+    /// How many plies were searched below this position
+    pub depth: u8,
+    pub bound: Bound,
+    /// The score relative to the side to move. Mate scores are relative to
+    /// this position, not to the root of the search.
+    pub score: i32,
+    pub best_move: Option<Move>,
+    generation: u8
+}
+
+/// A fixed-size hash table of searched positions, indexed by position key.
+/// Keep it around between searches to reuse the results of earlier
+/// iterations and moves.
+#[derive(Clone, Debug)]
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+    generation: u8
+}
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIZE)
+    }
+}
+impl TranspositionTable {
+    /// Create a table taking up roughly the specified number of megabytes. A
+    /// table of 0 megabytes never stores anything.
+    pub fn new(megabytes: usize) -> Self {
+        let len = megabytes * 1024 * 1024 / mem::size_of::<Option<Entry>>();
+        Self {
+            entries: vec![None; len],
+            generation: 0
+        }
+    }
+    /// Resize the table to roughly the specified number of megabytes. This
+    /// clears the table.
+    pub fn resize(&mut self, megabytes: usize) {
+        *self = Self::new(megabytes);
+    }
+    /// Forget all stored positions
+    pub fn clear(&mut self) {
+        for entry in &mut self.entries {
+            *entry = None;
+        }
+    }
+    /// Return the number of positions the table can hold
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Mark the start of a new search. Entries from earlier searches are
+    /// still used, but are replaced more eagerly.
+    pub fn new_search(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+    fn index(&self, key: u64) -> Option<usize> {
+        if self.entries.is_empty() {
+            None
+        } else {
+            Some((key % self.entries.len() as u64) as usize)
+        }
+    }
+    /// Look up the entry for a position
+    pub fn probe(&self, key: u64) -> Option<&Entry> {
+        let index = self.index(key)?;
+        self.entries[index].as_ref().filter(|entry| entry.key == key)
+    }
+    /// Store the result of searching a position. A deeper entry of another
+    /// position from the current search is kept rather than overwritten.
+    pub fn store(&mut self, key: u64, depth: u8, bound: Bound, score: i32, best_move: Option<Move>) {
+        let generation = self.generation;
+        let index = match self.index(key) {
+            Some(index) => index,
+            None => return
+        };
+        let slot = &mut self.entries[index];
+        if let Some(old) = slot {
+            if old.key != key && old.generation == generation && old.depth > depth {
+                return;
+            }
+        }
+        *slot = Some(Entry {
+            key,
+            depth,
+            bound,
+            score,
+            best_move,
+            generation
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{board::Board, Side};
+
+    /// A table with a single slot, so every key collides
+    fn tiny() -> TranspositionTable {
+        TranspositionTable {
+            entries: vec![None; 1],
+            generation: 0
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let m = Board::default().legal_moves(Side::White)[0];
+        let mut table = TranspositionTable::new(1);
+        assert!(table.probe(42).is_none());
+
+        table.store(42, 3, Bound::Lower, -17, Some(m));
+        let entry = table.probe(42).unwrap();
+        assert_eq!((entry.key, entry.depth, entry.bound, entry.score, entry.best_move), (42, 3, Bound::Lower, -17, Some(m)));
+
+        // The same position again replaces it, even with less depth
+        table.store(42, 1, Bound::Exact, 5, None);
+        let entry = table.probe(42).unwrap();
+        assert_eq!((entry.depth, entry.bound, entry.score, entry.best_move), (1, Bound::Exact, 5, None));
+    }
+    #[test]
+    fn collision() {
+        let mut table = tiny();
+        table.store(1, 3, Bound::Exact, 10, None);
+        // Same slot, different position
+        assert!(table.probe(2).is_none());
+        assert_eq!(table.probe(1).map(|entry| entry.score), Some(10));
+    }
+    #[test]
+    fn replacement() {
+        let mut table = tiny();
+        table.store(1, 3, Bound::Exact, 10, None);
+
+        // Within a search, the deeper entry is kept
+        table.store(2, 2, Bound::Exact, 20, None);
+        assert_eq!(table.probe(1).map(|entry| entry.depth), Some(3));
+        assert!(table.probe(2).is_none());
+
+        // As deep or deeper replaces it
+        table.store(2, 3, Bound::Exact, 20, None);
+        assert!(table.probe(1).is_none());
+        assert_eq!(table.probe(2).map(|entry| entry.score), Some(20));
+
+        // An entry from an earlier search is still found, but anything
+        // replaces it
+        table.new_search();
+        assert_eq!(table.probe(2).map(|entry| entry.score), Some(20));
+        table.store(3, 0, Bound::Upper, 30, None);
+        assert!(table.probe(2).is_none());
+        assert_eq!(table.probe(3).map(|entry| entry.score), Some(30));
+    }
+    #[test]
+    fn empty() {
+        let mut table = TranspositionTable::new(0);
+        assert_eq!(table.capacity(), 0);
+        table.store(1, 3, Bound::Exact, 10, None);
+        assert!(table.probe(1).is_none());
+    }
+    #[test]
+    fn resize_and_clear() {
+        let mut table = TranspositionTable::new(1);
+        let capacity = table.capacity();
+        assert!(capacity > 0);
+        table.store(1, 3, Bound::Exact, 10, None);
+
+        table.clear();
+        assert!(table.probe(1).is_none());
+        assert_eq!(table.capacity(), capacity);
+
+        table.store(1, 3, Bound::Exact, 10, None);
+        table.resize(2);
+        assert!(table.probe(1).is_none());
+        assert!(table.capacity() > capacity);
+        table.resize(0);
+        assert_eq!(table.capacity(), 0);
+    }
+}
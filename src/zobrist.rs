@@ -0,0 +1,233 @@
+use crate::{
+    board::{self, Board, Castling},
+    piece::{Piece, PieceKind},
+    Pos,
+    Side
+};
+
+const SQUARES: usize = (board::WIDTH * board::WIDTH) as usize;
+
+/// The random numbers that are combined into a Zobrist key
+struct Keys {
+    pieces: [[u64; SQUARES]; 12],
+    black_to_move: u64,
+    castling: [u64; 4],
+    en_passant: [u64; board::WIDTH as usize]
+}
+
+/// The increment of the SplitMix64 generator
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// The output function of the SplitMix64 generator. Good enough for Zobrist
+/// keys, and simple enough to run at compile time so every build uses the
+/// same keys.
+const fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+/// Fill in every key at compile time. Looping in a const fn is what makes
+/// Rust 1.46 the minimum supported version.
+const fn generate() -> Keys {
+    let mut state: u64 = 0x1234_5678_9ABC_DEF0;
+    let mut keys = Keys {
+        pieces: [[0; SQUARES]; 12],
+        black_to_move: 0,
+        castling: [0; 4],
+        en_passant: [0; board::WIDTH as usize]
+    };
+    let mut piece = 0;
+    while piece < keys.pieces.len() {
+        let mut square = 0;
+        while square < SQUARES {
+            state = state.wrapping_add(GOLDEN_GAMMA);
+            keys.pieces[piece][square] = mix(state);
+            square += 1;
+        }
+        piece += 1;
+    }
+    state = state.wrapping_add(GOLDEN_GAMMA);
+    keys.black_to_move = mix(state);
+    let mut i = 0;
+    while i < keys.castling.len() {
+        state = state.wrapping_add(GOLDEN_GAMMA);
+        keys.castling[i] = mix(state);
+        i += 1;
+    }
+    let mut i = 0;
+    while i < keys.en_passant.len() {
+        state = state.wrapping_add(GOLDEN_GAMMA);
+        keys.en_passant[i] = mix(state);
+        i += 1;
+    }
+    keys
+}
+static KEYS: Keys = generate();
+
+/// Return the key for a piece standing on a position
+pub(crate) fn piece(pos: Pos, piece: Piece) -> u64 {
+    let kind = match piece.kind {
+        PieceKind::Pawn => 0,
+        PieceKind::Knight => 1,
+        PieceKind::Bishop => 2,
+        PieceKind::Rook => 3,
+        PieceKind::Queen => 4,
+        PieceKind::King => 5
+    };
+    let side = match piece.side {
+        Side::Black => 0,
+        Side::White => 6
+    };
+    let Pos(x, y) = pos;
+    KEYS.pieces[side + kind][(y * board::WIDTH + x) as usize]
+}
+/// Return the key for the specified side to move
+pub(crate) fn side_to_move(side: Side) -> u64 {
+    match side {
+        Side::Black => KEYS.black_to_move,
+        Side::White => 0
+    }
+}
+/// Return the key for one side's castling rights
+pub(crate) fn castling(side: Side, castling: Castling) -> u64 {
+    let offset = match side {
+        Side::Black => 0,
+        Side::White => 2
+    };
+    let mut key = 0;
+    if castling.queenside { key ^= KEYS.castling[offset]; }
+    if castling.kingside { key ^= KEYS.castling[offset + 1]; }
+    key
+}
+/// Return the key for the pawn that can be captured en passant, if any
+pub(crate) fn en_passant(en_passant: Option<Pos>) -> u64 {
+    en_passant.map(|Pos(x, _)| KEYS.en_passant[x as usize]).unwrap_or(0)
+}
+
+impl Board {
+    /// Compute the Zobrist key of this board from scratch. The board keeps its
+    /// key up to date incrementally, so this is mostly useful for verifying
+    /// that, see `position_key`.
+    pub fn compute_zobrist(&self) -> u64 {
+        let mut key = 0;
+        for (y, row) in self.iter().enumerate() {
+            for (x, p) in row.iter().enumerate() {
+                if let Some(p) = *p {
+                    key ^= piece(Pos(x as i8, y as i8), p);
+                }
+            }
+        }
+        key ^= side_to_move(self.side_to_move);
+        key ^= castling(Side::Black, self.castling_black);
+        key ^= castling(Side::White, self.castling_white);
+        key ^= en_passant(self.en_passant);
+        key
+    }
+    /// Compute the Zobrist key of only the pawns from scratch, see `pawn_key`
+    pub fn compute_pawn_key(&self) -> u64 {
+        let mut key = 0;
+        for (y, row) in self.iter().enumerate() {
+            for (x, p) in row.iter().enumerate() {
+                if let Some(p) = p.filter(|p| p.kind == PieceKind::Pawn) {
+                    key ^= piece(Pos(x as i8, y as i8), p);
+                }
+            }
+        }
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        board::{Board, MoveFlags},
+        Pos
+    };
+
+    /// How many moves of each special kind were made while walking
+    #[derive(Default)]
+    struct Seen {
+        castling: u32,
+        en_passant: u32,
+        promotion: u32
+    }
+
+    fn assert_keys(board: &Board, context: &str) {
+        assert_eq!(board.position_key(), board.compute_zobrist(), "position key after {}: {}", context, board);
+        assert_eq!(board.pawn_key(), board.compute_pawn_key(), "pawn key after {}: {}", context, board);
+        if let Some(pawn) = board.en_passant() {
+            assert!(board.can_capture_en_passant(pawn), "useless en passant square after {}: {}", context, board);
+        }
+    }
+    /// Make and undo every legal move down to `depth`, comparing the
+    /// incremental keys to the ones computed from scratch along the way
+    fn walk(board: &mut Board, depth: u8, seen: &mut Seen) {
+        if depth == 0 {
+            return;
+        }
+        let before = (board.position_key(), board.pawn_key());
+        for m in board.legal_moves(board.side_to_move()) {
+            if m.is_castling() { seen.castling += 1; }
+            if m.flags.contains(MoveFlags::EN_PASSANT) { seen.en_passant += 1; }
+            if m.promotion.is_some() { seen.promotion += 1; }
+
+            let change = board.move_(m);
+            assert_keys(board, &format!("move {}", m));
+            walk(board, depth - 1, seen);
+            board.undo(change);
+            assert_keys(board, &format!("undoing {}", m));
+            assert_eq!((board.position_key(), board.pawn_key()), before, "undoing {} changed the keys", m);
+        }
+
+        let change = board.pass_turn();
+        assert_keys(board, "passing the turn");
+        board.undo(change);
+        assert_eq!((board.position_key(), board.pawn_key()), before, "undoing a pass changed the keys");
+    }
+
+    #[test]
+    fn incremental_keys() {
+        let positions = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 3),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 3),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4),
+            ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 3),
+            ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 3),
+            ("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3", 3)
+        ];
+        let mut seen = Seen::default();
+        for &(fen, depth) in &positions {
+            let mut board = Board::from_fen(fen).unwrap();
+            assert_keys(&board, "parsing");
+            walk(&mut board, depth, &mut seen);
+            assert_eq!(board.to_fen(), fen);
+        }
+        assert!(seen.castling > 0 && seen.en_passant > 0 && seen.promotion > 0);
+    }
+    #[test]
+    fn en_passant_only_if_capturable() {
+        let after = |fen, m: &str| {
+            let mut board = Board::from_fen(fen).unwrap();
+            let m = board.parse_move(m).unwrap();
+            board.move_(m);
+            board
+        };
+
+        // Nothing can capture the pawn, so the position is the same as if it
+        // had arrived there some other way
+        let board = after("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "e2e4");
+        assert_eq!(board.en_passant(), None);
+        let same = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        assert_eq!(board.position_key(), same.position_key());
+        let same = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+        assert_eq!(board.position_key(), same.position_key());
+
+        // A black pawn on d4 can, which makes it a different position
+        let board = after("rnbqkbnr/ppp1pppp/8/8/3p4/8/PPPPPPPP/RNBQKBNR w KQkq - 0 3", "e2e4");
+        assert_eq!(board.en_passant(), Some(Pos(4, 4)));
+        let without = Board::from_fen("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 3").unwrap();
+        assert_ne!(board.position_key(), without.position_key());
+        let with = Board::from_fen("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3").unwrap();
+        assert_eq!(board.position_key(), with.position_key());
+    }
+}
@@ -0,0 +1,47 @@
+use chess_minimax::{board::Board, fen::STARTING_POSITION};
+
+/// Check the perft numbers of a position, see
+/// https://www.chessprogramming.org/Perft_Results
+fn check(fen: &str, expected: &[u64]) {
+    let mut board = Board::from_fen(fen).unwrap();
+    for (depth, &nodes) in expected.iter().enumerate() {
+        assert_eq!(board.perft(depth as u8 + 1), nodes, "{} at depth {}", fen, depth + 1);
+    }
+    assert_eq!(board.to_fen(), Board::from_fen(fen).unwrap().to_fen(), "perft didn't undo all moves");
+}
+
+#[test]
+fn start_position() {
+    check(STARTING_POSITION, &[20, 400, 8902, 197_281]);
+}
+#[test]
+fn kiwipete() {
+    check("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &[48, 2039, 97_862]);
+}
+#[test]
+fn en_passant_pins() {
+    check("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812, 43_238]);
+}
+#[test]
+fn promotions_and_castling() {
+    check("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", &[6, 264, 9467]);
+}
+#[test]
+fn promotions_and_castling_mirrored() {
+    check("r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1", &[6, 264, 9467]);
+}
+#[test]
+fn discovered_checks() {
+    check("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", &[44, 1486, 62_379]);
+}
+#[test]
+fn middlegame() {
+    check("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", &[46, 2079, 89_890]);
+}
+#[test]
+fn divide_sums_up() {
+    let mut board = Board::from_fen(STARTING_POSITION).unwrap();
+    let divide = board.divide(3);
+    assert_eq!(divide.len(), 20);
+    assert_eq!(divide.iter().map(|&(_, nodes)| nodes).sum::<u64>(), 8902);
+}